@@ -0,0 +1,100 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use cs::{
+    format::InputFormat,
+    uniquify::{parse_stream, sort_and_print_stack, StackGroups},
+};
+
+const THREADS: usize = 10000;
+const FRAMES: usize = 30;
+
+/// eustack_fixture  -  eu-stack output of a process with THREADS threads, in 16 unique stacks.
+fn eustack_fixture() -> String {
+    let mut input = String::from("PID 14794 - process\n");
+    for tid in 0..THREADS {
+        input.push_str(&format!("TID {}:\n", 14794 + tid));
+        for level in 0..FRAMES {
+            input.push_str(&format!(
+                "#{:<2} 0x00007f83ddc5{:04x} func_{}_{}\n",
+                level,
+                level * 16,
+                tid % 16,
+                level
+            ));
+        }
+    }
+    input
+}
+
+/// gdb_fixture  -  gdb output of a process with THREADS threads, in 16 unique stacks.
+fn gdb_fixture() -> String {
+    let mut input = String::new();
+    for tid in 0..THREADS {
+        input.push_str(&format!("[New LWP {}]\n", 37746 + tid));
+    }
+    for tid in 0..THREADS {
+        input.push_str(&format!(
+            "\nThread {} (Thread 0x7f29ce816740 (LWP {}) \"test\"):\n",
+            THREADS - tid,
+            37746 + tid
+        ));
+        for level in 0..FRAMES {
+            input.push_str(&format!(
+                "#{:<2} 0x000055723be8{:04x} in func_{}_{} (arg=0x7ffec118b6f8) at test.c:{}\n",
+                level,
+                level * 16,
+                tid % 16,
+                level,
+                level + 1
+            ));
+        }
+    }
+    input.push_str("[Inferior 1 (process 37746) detached]\n");
+    input
+}
+
+fn bench_parse(c: &mut Criterion) {
+    for (name, input) in [("eu-stack", eustack_fixture()), ("gdb", gdb_fixture())] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.sample_size(20);
+
+        group.bench_function("detect", |b| {
+            b.iter(|| {
+                let mut groups = StackGroups::default();
+                parse_stream(input.as_bytes(), None, None, true, &mut groups).unwrap();
+                groups
+            })
+        });
+
+        let format = InputFormat::ALL.into_iter().find(|f| f.name() == name);
+        group.bench_function("raw", |b| {
+            b.iter(|| {
+                let mut groups = StackGroups::default();
+                parse_stream(input.as_bytes(), None, format, true, &mut groups).unwrap();
+                groups
+            })
+        });
+
+        group.bench_function("simplify", |b| {
+            b.iter(|| {
+                let mut groups = StackGroups::default();
+                parse_stream(input.as_bytes(), None, format, false, &mut groups).unwrap();
+                groups
+            })
+        });
+
+        group.bench_function("uniquify", |b| {
+            b.iter(|| {
+                let mut groups = StackGroups::default();
+                parse_stream(input.as_bytes(), None, format, false, &mut groups).unwrap();
+                sort_and_print_stack(groups.into_groups()).unwrap()
+            })
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
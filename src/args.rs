@@ -1,16 +1,39 @@
 use std::process::exit;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use regex::Regex;
+
+use crate::{
+    format::InputFormat,
+    trigger::{parse_trigger, Trigger},
+};
 
 #[derive(Parser, Clone)]
 #[command(long_about = None, about = "Tool to show call stack of process(es)",
     arg_required_else_help = true, version, trailing_var_arg=true,
+    disable_help_subcommand = true,
     after_help = r"Usages Examples:
   - `cs`:                       Choose process interactive and show's its call stack
   - `cs -l -u user`:            Show processes of USER.
   - `cs -p 905 -U`:             Show uniue stack for process `90588`
   - `cs -U -P google.chrome`:   Show unique stack of all processes of google chrome
+  - `cs -C -P google.chrome`:   Show unique stack of google chrome, grouped across all its processes
+  - `cs -U -p 905 --hide-frame __GI_`: Show unique stack for process 905, without frames of glibc internals
+  - `cs -U -p 905 --thread-state D`: Show unique stack of threads of process 905 in uninterruptible sleep
+  - `cs -U --symbols ./myapp --symbols /usr/lib/debug --maps maps.txt stack.txt`: Resolve frames without symbol of stack.txt
+  - `cs -U -G -L -p 905`:        Show unique stack for process 905, keeping file:line of frames
+  - `cs -U -G -p 905 --source 5`: Show unique stack for process 905 with 5 lines of source around its top frames
+  - `cs --locals -p 905`:        Show unique stack for process 905, then arguments and locals of chosen stacks
+  - `cs --tui -P google.chrome`: Browse unique stacks of all processes of google chrome in a terminal UI
+  - `cs --watch -p 905 -t 2`:   Show unique stacks of process 905 every 2 seconds, like `top`
   - `cs -U -p 905 -t 0.5 -n 3`: Get callstack for PID 905 for 3 times with interval 0.5 seconds, then uniquify the output.
+  - `cs --hot -p 905 -t 0.1 -n 100`: Sample process 905 100 times, then show its hottest functions like `perf report`
+  - `cs --timeline -p 905 -t 0.5 -n 10`: Sample process 905 10 times, then show the stack each thread is in at each sample
+  - `cs -U -p 905 --trigger cpu>90 --trigger dstate>5`: Show unique stack of process 905 once it spins or a thread hangs in D state
+  - `cs -U -p 905 --on-crash`:  Show unique stack of process 905 when it crashes, even without core dumps
+  - `cs -U -p 905 --signatures known-issues.txt`: Show unique stack of process 905, with known issues its stacks match
+  - `cs -U -p 905 --save`:      Show unique stack of process 905, and save it in the archive
+  - `cs history`, `cs -U history 1`: List snapshots of the archive, show unique stack of the latest one
 
 ")]
 pub struct Cli {
@@ -64,6 +87,11 @@ pub struct Cli {
     #[arg(short = 'U', long = "unique", default_value_t = false)]
     pub unique_mode: bool,
 
+    /// Combine mode: group identical stacks of all processes together, labelled with pid:tid
+    /// (implies -U)
+    #[arg(short = 'C', long = "combine", default_value_t = false)]
+    pub combine_mode: bool,
+
     /// gdb mode: use gdb to get call stack (default to eu-stack)
     #[arg(short = 'G', long = "gdb", default_value_t = false)]
     pub gdb_mode: bool,
@@ -72,6 +100,69 @@ pub struct Cli {
     #[arg(short = 'R', long = "raw", default_value_t = false)]
     pub raw_mode: bool,
 
+    /// Location mode: when simplifying callstacks, keep file:line of frames, shortened to basename or
+    /// relative to --source-root, and drop addresses
+    #[arg(short = 'L', long = "location", default_value_t = false)]
+    pub location_mode: bool,
+
+    /// Show source paths under DIR relative to it in location mode, can be repeated
+    #[arg(long = "source-root", value_name = "DIR")]
+    pub source_roots: Vec<String>,
+
+    /// gdb mode: capture arguments and local variables of frames of a process (`bt full`), and show
+    /// them for unique stack numbered N, or for stacks chosen interactively if N is not given
+    #[arg(long = "locals", value_name = "N", num_args = 0..=1, default_missing_value = "0")]
+    pub locals: Option<usize>,
+
+    /// Browse unique stacks of processes or files in a full-screen terminal UI (implies -C)
+    #[arg(long = "tui", default_value_t = false)]
+    pub tui: bool,
+
+    /// Watch mode: sample processes every interval (1 second by default) until interrupted, and
+    /// redraw their unique stacks, highlighting changes since the last refresh
+    #[arg(long = "watch", default_value_t = false, conflicts_with_all = ["tui", "locals", "count"])]
+    pub watch: bool,
+
+    /// Hot mode: when sampling processes, rank the N hottest functions (20 by default) by percentage of thread stacks
+    /// sampled they run in (exclusive) or are called in (inclusive). Replaces samples, unless -U
+    #[arg(long = "hot", value_name = "N", num_args = 0..=1, default_missing_value = "20",
+        conflicts_with_all = ["tui", "locals", "watch"])]
+    pub hot: Option<usize>,
+
+    /// Timeline mode: show which unique stack each thread is in at each sample, with times of
+    /// samples. Replaces samples, unless -U
+    #[arg(long = "timeline", default_value_t = false, requires = "interval",
+        conflicts_with_all = ["combine_mode", "locals", "watch"])]
+    pub timeline: bool,
+
+    /// Trigger mode: monitor processes, and capture their stacks only once CONDITION fires. Can be
+    /// repeated, any condition fires. CONDITION is `cpu>PERCENT` (CPU usage of process),
+    /// `dstate>SECONDS` (a thread in D state for that long), `rss+SIZE` (RSS grown by SIZE, with
+    /// K, M or G suffix) or `threads` (number of threads changed)
+    #[arg(long = "trigger", value_name = "CONDITION", value_parser = parse_trigger,
+        conflicts_with_all = ["core", "watch"])]
+    pub triggers: Vec<Trigger>,
+
+    /// Crash mode: wait for the process to get a fatal signal, and show its stacks right before it
+    /// dies. Processes that cannot be traced are waited for, and stacks are read from their core
+    #[arg(long = "on-crash", default_value_t = false,
+        conflicts_with_all = ["core", "watch", "triggers", "combine_mode", "locals", "interval"])]
+    pub on_crash: bool,
+
+    /// With --on-crash, capture stacks on SIGSEGV, SIGBUS, SIGILL, SIGFPE, SIGABRT or SIGSYS the
+    /// process has a handler for too, for crash handlers (breakpad...), which run once stacks are
+    /// captured. Not for runtimes catching SIGSEGV on purpose (JVM, Go)
+    #[arg(long = "crash-handler", default_value_t = false, requires = "on_crash")]
+    pub crash_handler: bool,
+
+    /// Save stacks captured, threads parsed from them and metadata of processes in a new snapshot of
+    /// archive DIR (~/.local/share/cs/history by default), listed by `cs history`
+    #[arg(long = "save", value_name = "DIR", num_args = 0..=1, default_missing_value = "")]
+    pub save: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Disable pager
     #[arg(short = 'N', long = "no-pager", default_value_t = false)]
     pub no_pager: bool,
@@ -80,13 +171,95 @@ pub struct Cli {
     #[arg(short = 'P', long = "pattern")]
     pub pattern: Option<String>,
 
+    /// Show only threads whose name matches REGEX when grouping stacks, can be repeated
+    #[arg(long = "thread-name", value_name = "REGEX", value_parser = Regex::new)]
+    pub thread_names: Vec<Regex>,
+
+    /// Show only threads having a frame matching REGEX when grouping stacks, can be repeated
+    #[arg(long = "with-frame", value_name = "REGEX", value_parser = Regex::new)]
+    pub with_frames: Vec<Regex>,
+
+    /// Show only threads in any of STATES (as in /proc, e.g. "RD" for running or disk sleep) when
+    /// grouping stacks of running processes
+    #[arg(long = "thread-state", value_name = "STATES")]
+    pub thread_states: Option<String>,
+
+    /// Hide frames matching REGEX when grouping stacks, can be repeated
+    #[arg(long = "hide-frame", value_name = "REGEX", value_parser = Regex::new)]
+    pub hide_frames: Vec<Regex>,
+
+    /// Show only frames matching REGEX when grouping stacks, can be repeated; threads without such
+    /// frames are not shown
+    #[arg(long = "only-frame", value_name = "REGEX", value_parser = Regex::new)]
+    pub only_frames: Vec<Regex>,
+
+    /// Collapse recursions: show runs of identical or cyclic frames once, with a repeat count
+    #[arg(long = "collapse", default_value_t = false)]
+    pub collapse: bool,
+
+    /// Do not demangle C++ and Rust symbols
+    #[arg(long = "no-demangle", default_value_t = false)]
+    pub no_demangle: bool,
+
+    /// Show template arguments of demangled symbols as <...>
+    #[arg(
+        long = "elide-templates",
+        default_value_t = false,
+        conflicts_with = "no_demangle"
+    )]
+    pub elide_templates: bool,
+
+    /// Resolve frames without symbol with executable, shared library, or debug directory (having
+    /// .build-id/xx/yyyy.debug files) at PATH, can be repeated
+    #[arg(long = "symbols", value_name = "PATH")]
+    pub symbols: Vec<String>,
+
+    /// Memory map (copy of /proc/PID/maps) of process that produced stack files, used with
+    /// --symbols; taken from COREFILE or running processes otherwise
+    #[arg(long = "maps", value_name = "FILE")]
+    pub maps: Option<String>,
+
+    /// Show N lines of source around top frames of each unique stack, for frames having file:line
+    /// (gdb, or frames resolved with --symbols); frames keep their full location, so it cannot be
+    /// used with --location or --source-root
+    #[arg(long = "source", value_name = "N", conflicts_with_all = ["location_mode", "source_roots"])]
+    pub source: Option<usize>,
+
+    /// Read source of files under FROM from TO instead, for builds done elsewhere, can be repeated
+    #[arg(long = "source-map", value_name = "FROM=TO", value_parser = parse_source_map)]
+    pub source_maps: Vec<(String, String)>,
+
+    /// Annotate unique stacks matching known issues of FILE, one per line: fingerprint (or
+    /// re:REGEX matched against functions of the stack, one per line), bug ID, and note
+    #[arg(long = "signatures", value_name = "FILE")]
+    pub signatures: Option<String>,
+
+    /// Show fingerprint of each unique stack, stable across addresses, builds and hosts
+    #[arg(long = "fingerprint", default_value_t = false)]
+    pub fingerprint: bool,
+
+    /// Format of stack files, detected automatically if not specified
+    #[arg(long = "input-format", value_enum)]
+    pub input_format: Option<InputFormat>,
+
     /// files to read stack from, use "-" for stdin; multiple files will be merged together.
     #[clap(allow_hyphen_values=true, num_args=0..,)]
     pub files: Vec<String>,
 }
 
-impl Cli {
-    pub fn default() -> Cli {
+/// Commands working on stacks captured before.
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// List snapshots of the archive saved to with --save, or show snapshot ID with the options given
+    /// before the command
+    History {
+        /// Snapshot to show, by number in the listing or by directory name
+        id: Option<String>,
+    },
+}
+
+impl Default for Cli {
+    fn default() -> Cli {
         Self {
             pids: None,
             core: None,
@@ -99,15 +272,50 @@ impl Cli {
             wide_mode: false,
             multi_mode: false,
             unique_mode: false,
+            combine_mode: false,
             gdb_mode: false,
             raw_mode: true,
+            location_mode: false,
+            source_roots: vec![],
+            locals: None,
+            tui: false,
+            watch: false,
+            hot: None,
+            timeline: false,
+            triggers: vec![],
+            on_crash: false,
+            crash_handler: false,
+            save: None,
+            command: None,
             files: vec![],
             no_pager: false,
             pattern: None,
+            input_format: None,
+            thread_names: vec![],
+            with_frames: vec![],
+            thread_states: None,
+            hide_frames: vec![],
+            only_frames: vec![],
+            collapse: false,
+            no_demangle: false,
+            elide_templates: false,
+            symbols: vec![],
+            maps: None,
+            source: None,
+            source_maps: vec![],
+            signatures: None,
+            fingerprint: false,
         }
     }
 }
 
+fn parse_source_map(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_owned(), to.to_owned())),
+        _ => Err(format!("expecting FROM=TO, got '{s}'")),
+    }
+}
+
 pub fn parse_args<T, S>(args: T) -> Cli
 where
     T: IntoIterator<Item = S>,
@@ -130,6 +338,19 @@ where
             }
         }
 
+        if cli.tui {
+            cli.combine_mode = true;
+        }
+
+        if cli.combine_mode {
+            cli.unique_mode = true;
+        }
+
+        if cli.locals.is_some() {
+            cli.gdb_mode = true;
+            cli.unique_mode = true;
+        }
+
         // check and update interval, minimum value should be 0.1s
         if let Some(interval) = cli.interval {
             if interval < 0.1 {
@@ -147,7 +368,7 @@ async fn test_parse_args() {
     assert_eq!(cli.pids.unwrap().first().unwrap(), "1000");
     assert!(!cli.unique_mode);
     assert!(cli.users.is_none());
-    assert!(cli.gdb_mode == false);
+    assert!(!cli.gdb_mode);
     assert!(cli.files.is_empty());
 
     let cli = parse_args(vec!["cs", "-U", "-c", "corefile"]);
@@ -166,7 +387,7 @@ async fn test_parse_args() {
     assert_eq!(cli.users.unwrap(), "someone");
 
     // conflict options
-    for args in vec![vec!["cs", "-c", "corefile", "-p", "1000"]] {
+    for args in [vec!["cs", "-c", "corefile", "-p", "1000"]] {
         match Cli::try_parse_from(args) {
             Ok(_) => {
                 panic!();
@@ -189,4 +410,207 @@ async fn test_parse_args() {
     let cli = parse_args(vec!["cs", "-t", "0.001", "-n", "3"]);
     assert_eq!(cli.interval.unwrap(), 0.1);
     assert_eq!(cli.count, 3);
+
+    let cli = parse_args(vec!["cs", "-C", "-P", "worker"]);
+    assert!(cli.combine_mode);
+    assert!(cli.unique_mode);
+
+    let cli = parse_args(vec!["cs", "-U", "--input-format", "gdb", "file-1"]);
+    assert_eq!(cli.input_format, Some(InputFormat::Gdb));
+    let cli = parse_args(vec!["cs", "-U", "--input-format", "eu-stack", "file-1"]);
+    assert_eq!(cli.input_format, Some(InputFormat::EuStack));
+    assert!(Cli::try_parse_from(vec!["cs", "--input-format", "perf", "file-1"]).is_err());
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "--hide-frame",
+        "__GI_",
+        "--hide-frame",
+        "libpthread",
+        "--only-frame",
+        "^myapp::",
+        "file-1",
+    ]);
+    assert_eq!(cli.hide_frames.len(), 2);
+    assert_eq!(cli.only_frames[0].as_str(), "^myapp::");
+    assert_eq!(cli.files, vec!["file-1"]);
+    assert!(Cli::try_parse_from(vec!["cs", "--hide-frame", "(", "file-1"]).is_err());
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "-p",
+        "1000",
+        "--thread-name",
+        "^worker",
+        "--with-frame",
+        "storage::",
+        "--thread-state",
+        "RD",
+    ]);
+    assert_eq!(cli.thread_names[0].as_str(), "^worker");
+    assert_eq!(cli.with_frames[0].as_str(), "storage::");
+    assert_eq!(cli.thread_states.as_deref(), Some("RD"));
+    assert!(!cli.collapse);
+
+    let cli = parse_args(vec!["cs", "-U", "--collapse", "file-1"]);
+    assert!(cli.collapse);
+    assert!(!cli.no_demangle);
+
+    let cli = parse_args(vec!["cs", "--no-demangle", "file-1"]);
+    assert!(cli.no_demangle);
+    let cli = parse_args(vec!["cs", "-U", "--elide-templates", "file-1"]);
+    assert!(cli.elide_templates);
+    assert!(
+        Cli::try_parse_from(vec!["cs", "--no-demangle", "--elide-templates", "file-1"]).is_err()
+    );
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "--symbols",
+        "/usr/lib/debug",
+        "--symbols",
+        "./myapp",
+        "--maps",
+        "maps.txt",
+        "file-1",
+    ]);
+    assert_eq!(cli.symbols, vec!["/usr/lib/debug", "./myapp"]);
+    assert_eq!(cli.maps.as_deref(), Some("maps.txt"));
+    assert_eq!(cli.files, vec!["file-1"]);
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "-G",
+        "-p",
+        "1000",
+        "--source",
+        "5",
+        "--source-map",
+        "/build/src=/home/me/src",
+    ]);
+    assert_eq!(cli.source, Some(5));
+    assert_eq!(
+        cli.source_maps,
+        vec![("/build/src".to_owned(), "/home/me/src".to_owned())]
+    );
+    assert!(Cli::try_parse_from(vec!["cs", "--source-map", "/build/src", "file-1"]).is_err());
+    assert!(Cli::try_parse_from(vec!["cs", "--source", "5", "-L", "file-1"]).is_err());
+    assert!(Cli::try_parse_from(vec![
+        "cs",
+        "--source",
+        "5",
+        "--source-root",
+        "/src",
+        "file-1"
+    ])
+    .is_err());
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "-p",
+        "1000",
+        "--signatures",
+        "known-issues.txt",
+        "--fingerprint",
+    ]);
+    assert_eq!(cli.signatures.as_deref(), Some("known-issues.txt"));
+    assert!(cli.fingerprint);
+    assert!(!parse_args(vec!["cs", "-U", "-p", "1000"]).fingerprint);
+
+    let cli = parse_args(vec![
+        "cs",
+        "-U",
+        "-L",
+        "--source-root",
+        "/home/me/repo",
+        "file-1",
+    ]);
+    assert!(cli.location_mode);
+    assert_eq!(cli.source_roots, vec!["/home/me/repo"]);
+
+    let cli = parse_args(vec!["cs", "--locals", "-p", "1000"]);
+    assert_eq!(cli.locals, Some(0));
+    assert!(cli.gdb_mode);
+    assert!(cli.unique_mode);
+    let cli = parse_args(vec!["cs", "-p", "1000", "--locals", "3"]);
+    assert_eq!(cli.locals, Some(3));
+    assert!(parse_args(vec!["cs", "-p", "1000"]).locals.is_none());
+
+    let cli = parse_args(vec!["cs", "--watch", "-p", "1000", "-t", "2"]);
+    assert!(cli.watch);
+    assert_eq!(cli.interval, Some(2.0));
+
+    let cli = parse_args(vec!["cs", "--hot", "-p", "1000", "-t", "0.1", "-n", "100"]);
+    assert_eq!(cli.hot, Some(20));
+    assert_eq!(cli.count, 100);
+    let cli = parse_args(vec!["cs", "-U", "-p", "1000", "--hot", "5"]);
+    assert_eq!(cli.hot, Some(5));
+
+    let cli = parse_args(vec![
+        "cs",
+        "--timeline",
+        "-p",
+        "1000",
+        "-t",
+        "0.5",
+        "-n",
+        "10",
+    ]);
+    assert!(cli.timeline);
+    assert!(Cli::try_parse_from(["cs", "--timeline", "-p", "1000"]).is_err());
+    assert!(Cli::try_parse_from(["cs", "--timeline", "-C", "-p", "1000", "-t", "1"]).is_err());
+
+    let cli = parse_args(vec![
+        "cs",
+        "-p",
+        "1000",
+        "--trigger",
+        "cpu>90",
+        "--trigger",
+        "threads",
+    ]);
+    assert_eq!(cli.triggers, vec![Trigger::Cpu(90.0), Trigger::Threads]);
+    assert!(Cli::try_parse_from(["cs", "-p", "1000", "--trigger", "cpu"]).is_err());
+
+    let cli = parse_args(vec!["cs", "-U", "-p", "1000", "--on-crash"]);
+    assert!(cli.on_crash);
+    assert!(Cli::try_parse_from(["cs", "-p", "1000", "--on-crash", "--watch"]).is_err());
+    let cli = parse_args(vec!["cs", "-p", "1000", "--on-crash", "--crash-handler"]);
+    assert!(cli.crash_handler);
+    assert!(Cli::try_parse_from(["cs", "-p", "1000", "--crash-handler"]).is_err());
+
+    let cli = parse_args(vec!["cs", "-p", "1000", "--save"]);
+    assert_eq!(cli.save.as_deref(), Some(""));
+    assert!(cli.command.is_none());
+    let cli = parse_args(vec!["cs", "-p", "1000", "--save", "/tmp/dumps"]);
+    assert_eq!(cli.save.as_deref(), Some("/tmp/dumps"));
+    assert_eq!(
+        parse_args(vec!["cs", "history"]).command,
+        Some(Command::History { id: None })
+    );
+    let cli = parse_args(vec!["cs", "-U", "--save", "/tmp/dumps", "history", "2"]);
+    assert_eq!(
+        cli.command,
+        Some(Command::History {
+            id: Some("2".to_owned())
+        })
+    );
+    assert!(cli.unique_mode);
+    assert!(cli.files.is_empty());
+    assert_eq!(cli.save.as_deref(), Some("/tmp/dumps"));
+    assert!(Cli::try_parse_from(["cs", "history", "1", "2"]).is_err());
+    // stack file named history
+    let cli = parse_args(vec!["cs", "-U", "--", "history"]);
+    assert!(cli.command.is_none());
+    assert_eq!(cli.files, vec!["history"]);
+
+    let cli = parse_args(vec!["cs", "--tui", "stack.txt"]);
+    assert!(cli.tui);
+    assert!(cli.combine_mode);
+    assert!(cli.unique_mode);
 }
@@ -0,0 +1,214 @@
+// Classification of threads by what they are doing, summarized per process before stacks.
+// Classes are told by the top frames of stacks, and by scheduler state read from /proc when
+// stacks tell nothing, e.g. for threads running user code.
+
+use std::collections::BTreeMap;
+
+use crate::{locks::lock_wait, patterns, stack::function_name};
+
+/// Number of frames, from the top of each stack, searched for waiting functions.
+const CLASS_FRAMES: usize = 4;
+
+/// What a thread is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadClass {
+    Running,
+    // waiting for events or work: condition variables, epoll, poll...
+    Idle,
+    Lock,
+    Io,
+    Sleeping,
+    // handling a fatal signal, or aborting
+    Crashed,
+}
+
+impl ThreadClass {
+    /// All classes, in the order they are printed.
+    pub const ALL: [ThreadClass; 6] = [
+        ThreadClass::Running,
+        ThreadClass::Idle,
+        ThreadClass::Lock,
+        ThreadClass::Io,
+        ThreadClass::Sleeping,
+        ThreadClass::Crashed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThreadClass::Running => "running",
+            ThreadClass::Idle => "idle",
+            ThreadClass::Lock => "lock",
+            ThreadClass::Io => "I/O",
+            ThreadClass::Sleeping => "sleeping",
+            ThreadClass::Crashed => "crashed",
+        }
+    }
+}
+
+/// stack_class  -  class of threads having `stack`, None if its top frames tell nothing.
+pub fn stack_class(stack: &str) -> Option<ThreadClass> {
+    if patterns::suspicious().is_match(stack) {
+        return Some(ThreadClass::Crashed);
+    }
+    if lock_wait(stack).is_some() {
+        return Some(ThreadClass::Lock);
+    }
+
+    let funcs: Vec<&str> = stack
+        .lines()
+        .filter(|s| patterns::frame_function().is_match(s))
+        .take(CLASS_FRAMES)
+        .map(function_name)
+        .collect();
+    [
+        (patterns::sleep_wait(), ThreadClass::Sleeping),
+        (patterns::lock_idle(), ThreadClass::Idle),
+        (patterns::idle_wait(), ThreadClass::Idle),
+        (patterns::io_wait(), ThreadClass::Io),
+    ]
+    .into_iter()
+    .find(|(r, _)| funcs.iter().any(|func| r.is_match(func)))
+    .map(|(_, class)| class)
+}
+
+/// classify  -  class of a thread, from class of its stack and its scheduler state.
+// Uninterruptible sleep is I/O whatever the stack says, unless the thread waits for a lock.
+pub fn classify(stack_class: Option<ThreadClass>, state: Option<char>) -> ThreadClass {
+    match (stack_class, state) {
+        (Some(class @ (ThreadClass::Crashed | ThreadClass::Lock)), _) => class,
+        (_, Some('D')) => ThreadClass::Io,
+        (Some(class), _) => class,
+        (None, Some('S' | 'I')) => ThreadClass::Idle,
+        (None, _) => ThreadClass::Running,
+    }
+}
+
+/// Number of threads of each class, per process.
+#[derive(Debug, Default)]
+pub struct ClassSummary {
+    rows: BTreeMap<String, [usize; ThreadClass::ALL.len()]>,
+}
+
+impl ClassSummary {
+    pub fn add(&mut self, process: &str, class: ThreadClass) {
+        let i = ThreadClass::ALL.iter().position(|c| *c == class).unwrap();
+        self.rows.entry(process.to_owned()).or_default()[i] += 1;
+    }
+
+    pub fn count(&self, process: &str, class: ThreadClass) -> usize {
+        let i = ThreadClass::ALL.iter().position(|c| *c == class).unwrap();
+        self.rows.get(process).map_or(0, |row| row[i])
+    }
+
+    /// print  -  table of classes of threads, one row per process.
+    pub fn print(&self) -> String {
+        if self.rows.is_empty() {
+            return String::new();
+        }
+
+        let width = self
+            .rows
+            .keys()
+            .map(|p| p.len())
+            .chain(["Process".len()])
+            .max()
+            .unwrap();
+        let mut output = format!("{:<width$}  Threads", "Process");
+        for class in ThreadClass::ALL {
+            output.push_str(&format!("  {:>8}", class.name()));
+        }
+        output.push('\n');
+        for (process, row) in self.rows.iter() {
+            output.push_str(&format!(
+                "{process:<width$}  {:>7}",
+                row.iter().sum::<usize>()
+            ));
+            for count in row {
+                output.push_str(&format!("  {count:>8}"));
+            }
+            output.push('\n');
+        }
+        output
+    }
+}
+
+#[test]
+fn test_classify() {
+    for (stack, class) in [
+        (
+            "#0  0x00007f29ce8db9e7 in clock_nanosleep@GLIBC_2.2.5 () from /usr/lib64/libc.so.6\n\
+             #1  0x00007f29ce8e2a55 in __nanosleep () from /usr/lib64/libc.so.6\n\
+             #2  0x000055723be89162 in func2 () at test.c:5\n",
+            Some(ThreadClass::Sleeping),
+        ),
+        (
+            "#0  0x00007f83ddc5363f __poll\n#1  0x00007f83de32a8d7 g_main_context_iterate\n",
+            Some(ThreadClass::Idle),
+        ),
+        (
+            "#0  0x00007f83ddc5363f epoll_wait\n#1  0x000055723be89162 run\n",
+            Some(ThreadClass::Idle),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 futex_wait\n\
+             #1  0x00007f29ce8db9e7 __futex_abstimed_wait_common\n\
+             #2  0x00007f29ce8e2a55 ___pthread_cond_timedwait\n",
+            Some(ThreadClass::Idle),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 __lll_lock_wait\n#1  0x00007f29ce8e2a55 pthread_mutex_lock\n",
+            Some(ThreadClass::Lock),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 __libc_pread64\n#1  0x000055723be89162 load\n",
+            Some(ThreadClass::Io),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 in __GI___libc_write () from /usr/lib64/libc.so.6\n",
+            Some(ThreadClass::Io),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 __pthread_kill_implementation\n\
+             #1  0x00007f29ce8db9e7 raise\n\
+             #2  0x00007f29ce8db9e7 abort\n",
+            Some(ThreadClass::Crashed),
+        ),
+        (
+            "#0  0x000055723be89162 compute\n#1  0x000055723be8917d main\n",
+            None,
+        ),
+    ] {
+        assert_eq!(stack_class(stack), class, "{stack}");
+    }
+
+    assert_eq!(classify(None, None), ThreadClass::Running);
+    assert_eq!(classify(None, Some('R')), ThreadClass::Running);
+    assert_eq!(classify(None, Some('S')), ThreadClass::Idle);
+    assert_eq!(
+        classify(Some(ThreadClass::Idle), Some('D')),
+        ThreadClass::Io
+    );
+    assert_eq!(
+        classify(Some(ThreadClass::Lock), Some('D')),
+        ThreadClass::Lock
+    );
+    assert_eq!(
+        classify(Some(ThreadClass::Sleeping), Some('R')),
+        ThreadClass::Sleeping
+    );
+
+    let mut summary = ClassSummary::default();
+    assert_eq!(summary.print(), "");
+    summary.add("37746", ThreadClass::Idle);
+    summary.add("37746", ThreadClass::Idle);
+    summary.add("37746", ThreadClass::Lock);
+    summary.add("5", ThreadClass::Running);
+    assert_eq!(summary.count("37746", ThreadClass::Idle), 2);
+    assert_eq!(summary.count("37746", ThreadClass::Io), 0);
+    assert_eq!(
+        summary.print(),
+        "Process  Threads   running      idle      lock       I/O  sleeping   crashed\n\
+         37746          3         0         2         1         0         0         0\n\
+         5              1         1         0         0         0         0         0\n"
+    );
+}
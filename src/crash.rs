@@ -0,0 +1,568 @@
+// Capture on crash: trace all threads of a process with `PTRACE_SEIZE`, which neither stops nor
+// signals it, until one of them gets a fatal signal. The process is then held stopped while stacks
+// are captured, and the signal is delivered again so it dies as it would have.
+// Signals the process has a handler for are let through, unless asked to capture crash signals
+// caught by crash handlers as well: the handler runs once stacks are captured.
+// Processes that cannot be traced are waited for, and stacks are read from their core file.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::exit,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    args::Cli,
+    eu_stack::{self, run_eustack},
+    filter::StackFilter,
+    gdb,
+    procfs::read_task_stats,
+    sample::Report,
+    utils::setup_pager,
+};
+
+/// Time given to a process to stop, once all its threads are detached.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time given to the kernel to write the core file, once the process is gone.
+const CORE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Signals whose default action terminates the process.
+const FATAL_SIGNALS: [(libc::c_int, &str); 21] = [
+    (libc::SIGHUP, "SIGHUP"),
+    (libc::SIGINT, "SIGINT"),
+    (libc::SIGQUIT, "SIGQUIT"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGUSR1, "SIGUSR1"),
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGUSR2, "SIGUSR2"),
+    (libc::SIGPIPE, "SIGPIPE"),
+    (libc::SIGALRM, "SIGALRM"),
+    (libc::SIGTERM, "SIGTERM"),
+    (libc::SIGSTKFLT, "SIGSTKFLT"),
+    (libc::SIGXCPU, "SIGXCPU"),
+    (libc::SIGXFSZ, "SIGXFSZ"),
+    (libc::SIGVTALRM, "SIGVTALRM"),
+    (libc::SIGPROF, "SIGPROF"),
+    (libc::SIGPWR, "SIGPWR"),
+    (libc::SIGSYS, "SIGSYS"),
+];
+
+/// Signals of crashes, caught by crash handlers (breakpad, backtrace on SIGSEGV...).
+const CRASH_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGILL,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGSEGV,
+    libc::SIGSYS,
+];
+
+/// Signals raised again when the instruction faulting is run again, as it is when the thread
+/// resumes without the signal.
+const FAULT_SIGNALS: [libc::c_int; 4] = [libc::SIGILL, libc::SIGBUS, libc::SIGFPE, libc::SIGSEGV];
+
+/// signal_name  -  name of signal `sig`.
+pub fn signal_name(sig: libc::c_int) -> String {
+    FATAL_SIGNALS
+        .iter()
+        .find(|(s, _)| *s == sig)
+        .map_or(format!("signal {sig}"), |(_, name)| name.to_string())
+}
+
+/// parse_sig_mask  -  signal mask `field` (SigCgt, SigIgn...) of content of `/proc/<pid>/status`.
+pub fn parse_sig_mask(content: &str, field: &str) -> u64 {
+    content
+        .lines()
+        .find_map(|s| s.strip_prefix(field)?.strip_prefix(':'))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .unwrap_or(0)
+}
+
+/// is_fatal  -  if signal `sig` kills a process catching signals of mask `caught`, and ignoring
+/// those of mask `ignored`. Crash signals caught count as fatal too with `handlers`.
+// Runtimes catch SIGSEGV on purpose (JVM null checks, Go nil pointers recovered), thus crash
+// handlers are not assumed.
+pub fn is_fatal(sig: libc::c_int, caught: u64, ignored: u64, handlers: bool) -> bool {
+    let bit = 1u64 << (sig - 1);
+    if handlers && CRASH_SIGNALS.contains(&sig) {
+        return ignored & bit == 0;
+    }
+    FATAL_SIGNALS.iter().any(|(s, _)| *s == sig) && (caught | ignored) & bit == 0
+}
+
+/// core_glob  -  glob pattern of path of the core file of process `pid`, from `core_pattern` of
+/// the kernel. None if cores are handed to a program (systemd-coredump, apport...).
+// Specifiers that cannot be predicted (time, uid, hostname...) are matched by any text.
+pub fn core_glob(
+    pattern: &str,
+    uses_pid: bool,
+    pid: &str,
+    comm: &str,
+    cwd: &Path,
+) -> Option<String> {
+    if pattern.starts_with('|') {
+        return None;
+    }
+
+    let mut path = String::new();
+    let mut has_pid = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            path.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => path.push('%'),
+            Some('p' | 'P' | 'i' | 'I') => {
+                has_pid = true;
+                path.push_str(pid);
+            }
+            Some('e') => path.push_str(comm),
+            Some(_) => path.push('*'),
+            None => {}
+        }
+    }
+    if uses_pid && !has_pid {
+        path.push_str(&format!(".{pid}"));
+    }
+    Some(cwd.join(path).to_string_lossy().to_string())
+}
+
+/// ptrace  -  run ptrace `request` on thread `tid`.
+fn ptrace(request: libc::c_uint, tid: libc::pid_t, data: usize) -> Result<(), String> {
+    let null = std::ptr::null_mut::<libc::c_void>();
+    // SAFETY: requests used here take no pointer to memory of this process.
+    let r = unsafe { libc::ptrace(request, tid, null, data as *mut libc::c_void) };
+    if r == -1 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+/// is_fault  -  if signal got by thread `tid`, stopped in signal-delivery-stop, is raised by the
+/// kernel on an instruction faulting, rather than sent.
+fn is_fault(tid: libc::pid_t, sig: libc::c_int) -> bool {
+    // SAFETY: siginfo_t is plain data, all zeroes is valid.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let null = std::ptr::null_mut::<libc::c_void>();
+    // SAFETY: info is a valid pointer to a siginfo_t, as the request expects.
+    let r = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETSIGINFO,
+            tid,
+            null,
+            &mut info as *mut libc::siginfo_t as *mut libc::c_void,
+        )
+    };
+    // si_code is positive for signals raised by the kernel, SI_USER, SI_TKILL... otherwise
+    r != -1 && info.si_code > 0 && FAULT_SIGNALS.contains(&sig)
+}
+
+/// event_msg  -  message of the last ptrace event of thread `tid`, the new tid for clone events.
+fn event_msg(tid: libc::pid_t) -> Result<libc::c_ulong, String> {
+    let mut msg: libc::c_ulong = 0;
+    let null = std::ptr::null_mut::<libc::c_void>();
+    // SAFETY: msg is a valid pointer to an unsigned long, as the request expects.
+    let r = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETEVENTMSG,
+            tid,
+            null,
+            &mut msg as *mut libc::c_ulong as *mut libc::c_void,
+        )
+    };
+    if r == -1 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(msg)
+}
+
+/// wait_any  -  wait for a state change of any thread traced, returns its tid and wait status.
+// Stacks are captured once tracing ends, thus threads traced are the only children then.
+fn wait_any() -> Result<(libc::pid_t, libc::c_int), String> {
+    let mut status = 0;
+    // SAFETY: status is a valid pointer.
+    let tid = unsafe { libc::waitpid(-1, &mut status, libc::__WALL) };
+    if tid == -1 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok((tid, status))
+}
+
+/// task_ids  -  threads of process `pid`.
+fn task_ids(pid: libc::pid_t) -> HashSet<libc::pid_t> {
+    std::fs::read_dir(format!("/proc/{pid}/task"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.file_name().to_string_lossy().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// What ended tracing of a process.
+#[derive(Debug, PartialEq)]
+pub enum Crash {
+    // thread, fatal signal it got, and if the signal is a fault raised again when the thread
+    // resumes, the process is left stopped
+    Signal(libc::pid_t, libc::c_int, bool),
+    // the process exited without fatal signal, as described
+    Exited(String),
+}
+
+/// Threads of a process traced.
+struct Tracer {
+    pid: libc::pid_t,
+    threads: HashSet<libc::pid_t>,
+    /// Capture crash signals caught by handlers too.
+    handlers: bool,
+}
+
+impl Tracer {
+    /// seize  -  trace all threads of process `pid`, threads created later are traced too.
+    // Threads are listed again until none is missing, as they may be created meanwhile.
+    fn seize(pid: libc::pid_t, handlers: bool) -> Result<Tracer, String> {
+        let options = libc::PTRACE_O_TRACECLONE | libc::PTRACE_O_TRACEEXEC;
+        ptrace(libc::PTRACE_SEIZE, pid, options as usize)?;
+        let mut tracer = Tracer {
+            pid,
+            threads: HashSet::from([pid]),
+            handlers,
+        };
+        loop {
+            let new: Vec<libc::pid_t> = task_ids(pid)
+                .into_iter()
+                .filter(|tid| !tracer.threads.contains(tid))
+                .collect();
+            if new.is_empty() {
+                return Ok(tracer);
+            }
+            for tid in new {
+                // exited meanwhile, or traced already as a clone of a thread traced
+                let _ = ptrace(libc::PTRACE_SEIZE, tid, options as usize);
+                tracer.threads.insert(tid);
+            }
+        }
+    }
+
+    /// wait_crash  -  let threads run until one gets a fatal signal, or the process exits.
+    fn wait_crash(&mut self) -> Result<Crash, String> {
+        loop {
+            let (tid, status) = wait_any()?;
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.threads.remove(&tid);
+                if self.threads.is_empty() || tid == self.pid {
+                    return Ok(Crash::Exited(if libc::WIFEXITED(status) {
+                        format!("exit code {}", libc::WEXITSTATUS(status))
+                    } else {
+                        signal_name(libc::WTERMSIG(status))
+                    }));
+                }
+                continue;
+            }
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+
+            // first stop of a new thread may come before the clone event of its creator
+            self.threads.insert(tid);
+            let sig = libc::WSTOPSIG(status);
+            match status >> 16 {
+                // group-stop, threads stay stopped until SIGCONT
+                libc::PTRACE_EVENT_STOP
+                    if matches!(
+                        sig,
+                        libc::SIGSTOP | libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU
+                    ) =>
+                {
+                    let _ = ptrace(libc::PTRACE_LISTEN, tid, 0);
+                }
+                // new thread, traced already, waited for from now on
+                libc::PTRACE_EVENT_CLONE => {
+                    if let Ok(new) = event_msg(tid) {
+                        self.threads.insert(new as libc::pid_t);
+                    }
+                    let _ = ptrace(libc::PTRACE_CONT, tid, 0);
+                }
+                0 if !is_fatal(
+                    sig,
+                    self.sig_mask("SigCgt"),
+                    self.sig_mask("SigIgn"),
+                    self.handlers,
+                ) =>
+                {
+                    let _ = ptrace(libc::PTRACE_CONT, tid, sig as usize);
+                }
+                0 => {
+                    let fault = is_fault(tid, sig);
+                    self.stop(tid)?;
+                    return Ok(Crash::Signal(tid, sig, fault));
+                }
+                // first stop of new threads, exec events
+                _ => {
+                    let _ = ptrace(libc::PTRACE_CONT, tid, 0);
+                }
+            }
+        }
+    }
+
+    fn sig_mask(&self, field: &str) -> u64 {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", self.pid));
+        parse_sig_mask(&status.unwrap_or_default(), field)
+    }
+
+    /// stop  -  leave the process stopped once `crashed` got a fatal signal, and stop tracing it.
+    // All threads are interrupted, then SIGSTOP is queued before detaching them: it is the first
+    // signal handled when they resume, before the fatal one is handled again. The fatal signal is
+    // discarded, and other signals caught meanwhile are delivered as they are detached.
+    fn stop(&mut self, crashed: libc::pid_t) -> Result<(), String> {
+        let mut stopped: HashMap<libc::pid_t, libc::c_int> = HashMap::from([(crashed, 0)]);
+        for tid in self.threads.iter().filter(|tid| **tid != crashed) {
+            let _ = ptrace(libc::PTRACE_INTERRUPT, *tid, 0);
+        }
+        while stopped.len() < self.threads.len() {
+            let (tid, status) = wait_any()?;
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.threads.remove(&tid);
+            } else if libc::WIFSTOPPED(status) {
+                self.threads.insert(tid);
+                let sig = match status >> 16 {
+                    0 => libc::WSTOPSIG(status),
+                    libc::PTRACE_EVENT_CLONE => {
+                        if let Ok(new) = event_msg(tid) {
+                            self.threads.insert(new as libc::pid_t);
+                        }
+                        0
+                    }
+                    _ => 0,
+                };
+                stopped.insert(tid, sig);
+            }
+        }
+
+        // SAFETY: plain system calls.
+        unsafe { libc::kill(self.pid, libc::SIGSTOP) };
+        for (tid, sig) in stopped {
+            let _ = ptrace(libc::PTRACE_DETACH, tid, sig as usize);
+        }
+        Ok(())
+    }
+}
+
+/// wait_stopped  -  wait for all threads of process `pid` to stop.
+async fn wait_stopped(pid: &str) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < STOP_TIMEOUT {
+        let tasks = read_task_stats(pid);
+        if !tasks.is_empty() && tasks.values().all(|t| t.state == 'T') {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+/// wait_core  -  wait for process `pid` to exit, then for its core file to be written.
+async fn wait_core(pid: &str) -> Result<(PathBuf, Option<String>), String> {
+    let read = |path: String| std::fs::read_to_string(path).unwrap_or_default();
+    let comm = read(format!("/proc/{pid}/comm")).trim().to_owned();
+    let exe = std::fs::read_link(format!("/proc/{pid}/exe")).ok();
+    let cwd = std::fs::read_link(format!("/proc/{pid}/cwd")).unwrap_or_default();
+    let pattern = read("/proc/sys/kernel/core_pattern".to_owned());
+    let uses_pid = read("/proc/sys/kernel/core_uses_pid".to_owned()).trim() == "1";
+    let Some(path) = core_glob(pattern.trim(), uses_pid, pid, &comm, &cwd) else {
+        return Err(format!(
+            "core files are handed to '{}', look for it there (e.g. with coredumpctl)",
+            pattern.trim().trim_start_matches('|')
+        ));
+    };
+
+    println!("Waiting for process {pid} to exit, and its core file {path}");
+    // a process stays a zombie until its parent reaps it, its core file is written by then
+    while read_task_stats(pid)
+        .get(pid)
+        .is_some_and(|t| t.state != 'Z')
+    {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // the kernel writes the core file while the process exits, files older than that are stale
+    let exited = SystemTime::now() - Duration::from_secs(1);
+    let start = Instant::now();
+    while start.elapsed() < CORE_TIMEOUT {
+        let core = glob::glob(&path)
+            .map_err(|err| err.to_string())?
+            .flatten()
+            .filter_map(|p| Some((std::fs::metadata(&p).ok()?, p)))
+            .filter(|(m, _)| m.modified().is_ok_and(|t| t >= exited))
+            .max_by_key(|(m, _)| m.modified().ok());
+        if let Some((metadata, core)) = core {
+            // still being written if it grows
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if std::fs::metadata(&core).is_ok_and(|m| m.len() == metadata.len()) {
+                let exe = exe.map(|p| p.to_string_lossy().to_string());
+                return Ok((core, exe));
+            }
+            continue;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    Err("process exited without core file, are core dumps enabled (ulimit -c)?".to_owned())
+}
+
+/// run_on_crash  -  wait for process of `cli` to get a fatal signal, and show its stacks then.
+pub async fn run_on_crash(cli: &Cli) {
+    let pid = match cli.pids.as_deref() {
+        Some([pid]) => pid.to_owned(),
+        _ => {
+            eprintln!("Needs one pid to wait for its crash.");
+            exit(2);
+        }
+    };
+    let Ok(id) = pid.parse::<libc::pid_t>() else {
+        eprintln!("Invalid pid: {pid}");
+        exit(2);
+    };
+
+    // ptrace requests must all come from the thread tracing
+    let handlers = cli.crash_handler;
+    let traced = tokio::task::spawn_blocking(move || {
+        Tracer::seize(id, handlers).and_then(|mut tracer| tracer.wait_crash())
+    })
+    .await
+    .unwrap();
+    match traced {
+        Ok(Crash::Signal(tid, sig, fault)) => {
+            println!(
+                "Process {pid} got {} in thread {tid}, capturing stacks.",
+                signal_name(sig)
+            );
+            if !wait_stopped(&pid).await {
+                eprintln!("Process {pid} did not stop, stacks may be partial.");
+            }
+            let filter = StackFilter::from_cli(cli);
+            let report = Report::from_cli(cli);
+            let result = if cli.gdb_mode {
+                gdb::do_run_gdb(&pid, &filter, cli.raw_mode, None, 1, report).await
+            } else {
+                let args = vec!["-p".to_owned(), pid.clone()];
+                eu_stack::do_run_eustack(args, Some(&pid), &filter, None, 1, report).await
+            };
+
+            // a fault is raised again, with its details for handlers, as the instruction is run again
+            // SAFETY: plain system calls.
+            unsafe {
+                if !fault {
+                    libc::syscall(libc::SYS_tgkill, id, tid, sig);
+                }
+                libc::kill(id, libc::SIGCONT);
+            }
+            match result {
+                Ok(output) => {
+                    setup_pager(cli);
+                    println!("{output}");
+                    exit(0);
+                }
+                Err(err) => {
+                    eprintln!("Failed to capture stacks of process {pid}: {err}");
+                    exit(2);
+                }
+            }
+        }
+        Ok(Crash::Exited(how)) => {
+            eprintln!("Process {pid} exited without fatal signal: {how}");
+            exit(1);
+        }
+        Err(err) => {
+            eprintln!("Failed to trace process {pid}: {err}, waiting for its core file instead.");
+        }
+    }
+
+    match wait_core(&pid).await {
+        Ok((core, exe)) => {
+            println!("Reading core file {}", core.display());
+            let mut cli = cli.clone();
+            cli.pids = None;
+            cli.core = Some(core.to_string_lossy().to_string());
+            cli.executable = exe;
+            run_eustack(&cli).await;
+        }
+        Err(err) => {
+            eprintln!("Failed to get core file of process {pid}: {err}");
+            exit(2);
+        }
+    }
+}
+
+#[test]
+fn test_signals() {
+    assert_eq!(signal_name(libc::SIGSEGV), "SIGSEGV");
+    assert_eq!(
+        signal_name(libc::SIGCHLD),
+        format!("signal {}", libc::SIGCHLD)
+    );
+
+    let status = "Name:\tcs\nSigBlk:\t0000000000000000\nSigIgn:\t0000000000001000\n\
+                  SigCgt:\t0000000180004002\n";
+    let caught = parse_sig_mask(status, "SigCgt");
+    let ignored = parse_sig_mask(status, "SigIgn");
+    assert_eq!(caught, 0x180004002);
+    assert_eq!(parse_sig_mask(status, "SigQ"), 0);
+
+    assert!(is_fatal(libc::SIGSEGV, caught, ignored, false));
+    assert!(is_fatal(libc::SIGABRT, caught, ignored, false));
+    // caught by handler
+    assert!(!is_fatal(libc::SIGINT, caught, ignored, false));
+    assert!(!is_fatal(libc::SIGTERM, caught, ignored, false));
+    // ignored
+    assert!(!is_fatal(libc::SIGPIPE, caught, ignored, false));
+    // not terminating
+    assert!(!is_fatal(libc::SIGCHLD, 0, 0, false));
+    assert!(!is_fatal(libc::SIGSTOP, 0, 0, false));
+
+    // crash handler, only crash signals caught are captured
+    let caught = caught | 1 << (libc::SIGSEGV - 1);
+    assert!(!is_fatal(libc::SIGSEGV, caught, ignored, false));
+    assert!(is_fatal(libc::SIGSEGV, caught, ignored, true));
+    assert!(!is_fatal(libc::SIGTERM, caught, ignored, true));
+    assert!(!is_fatal(libc::SIGPIPE, caught, ignored, true));
+}
+
+#[test]
+fn test_core_glob() {
+    let cwd = Path::new("/srv/app");
+    assert_eq!(
+        core_glob("core", false, "905", "app", cwd).as_deref(),
+        Some("/srv/app/core")
+    );
+    assert_eq!(
+        core_glob("core", true, "905", "app", cwd).as_deref(),
+        Some("/srv/app/core.905")
+    );
+    assert_eq!(
+        core_glob("/var/crash/core.%e.%p.%t", true, "905", "app", cwd).as_deref(),
+        Some("/var/crash/core.app.905.*")
+    );
+    assert_eq!(
+        core_glob("/tmp/%%core-%u", false, "905", "app", cwd).as_deref(),
+        Some("/tmp/%core-*")
+    );
+    assert_eq!(
+        core_glob(
+            "|/usr/lib/systemd/systemd-coredump %P %u",
+            false,
+            "905",
+            "app",
+            cwd
+        ),
+        None
+    );
+}
@@ -0,0 +1,199 @@
+// Demangling of C++ (Itanium ABI) and Rust (legacy and v0) symbols found in frames.
+// eu-stack prints mangled names unless asked not to, and dumps produced by other tools often
+// contain them as well.
+
+use cpp_demangle::{DemangleOptions, Symbol};
+
+use crate::args::Cli;
+
+/// How symbols in frames are demangled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Demangle {
+    // keep symbols as they are
+    Off,
+    #[default]
+    Full,
+    // demangle, and replace template arguments with `<...>`
+    ElideTemplates,
+}
+
+impl Demangle {
+    pub fn from_cli(cli: &Cli) -> Demangle {
+        if cli.no_demangle {
+            Demangle::Off
+        } else if cli.elide_templates {
+            Demangle::ElideTemplates
+        } else {
+            Demangle::Full
+        }
+    }
+
+    /// line  -  demangle every mangled symbol found in `s`.
+    pub fn line(&self, s: &str) -> String {
+        if *self == Demangle::Off || !(s.contains("_Z") || s.contains("_R")) {
+            return s.to_owned();
+        }
+
+        let is_symbol_char =
+            |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.';
+        let mut result = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(start) = rest.find('_') {
+            let (before, candidate) = rest.split_at(start);
+            result.push_str(before);
+
+            // symbols start at word boundary
+            let len = candidate
+                .find(|c: char| !is_symbol_char(c))
+                .unwrap_or(candidate.len());
+            let (token, after) = candidate.split_at(len);
+            let at_boundary = !result.ends_with(is_symbol_char);
+            match demangle_symbol(token).filter(|_| at_boundary) {
+                Some(name) if *self == Demangle::ElideTemplates => {
+                    result.push_str(&elide_templates(&name))
+                }
+                Some(name) => result.push_str(&name),
+                None => result.push_str(token),
+            }
+            rest = after;
+        }
+        result.push_str(rest);
+
+        result
+    }
+
+    /// stack  -  demangle every line of `input`.
+    pub fn stack(&self, input: String) -> String {
+        if *self == Demangle::Off {
+            return input;
+        }
+
+        input
+            .split('\n')
+            .map(|s| self.line(s))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// demangle_symbol  -  demangle `symbol`, returns None if it is not a mangled name.
+// Rust legacy symbols are valid Itanium symbols too, thus Rust is tried first, printed without the
+// trailing hash.
+pub fn demangle_symbol(symbol: &str) -> Option<String> {
+    if symbol.starts_with("_R") || symbol.starts_with("_ZN") {
+        if let Ok(name) = rustc_demangle::try_demangle(symbol) {
+            return Some(format!("{name:#}"));
+        }
+    }
+
+    if symbol.starts_with("_Z") {
+        return Symbol::new(symbol)
+            .ok()
+            .and_then(|sym| sym.demangle(&DemangleOptions::new()).ok());
+    }
+
+    None
+}
+
+/// elide_templates  -  replace template arguments in demangled `name` with `<...>`.
+// `<` and `>` of operators (`operator<<`, `operator->`...) are not template brackets.
+pub fn elide_templates(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut depth = 0;
+    let mut chars = name.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if depth == 0 && name[..i].ends_with("operator") {
+            result.push(c);
+            while let Some((_, c)) = chars.next_if(|(_, c)| "<>=-".contains(*c)) {
+                result.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            '<' => {
+                if depth == 0 {
+                    result.push_str("<...");
+                }
+                depth += 1;
+            }
+            '>' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    result.push('>');
+                }
+            }
+            _ if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+
+    result
+}
+
+#[test]
+fn test_demangle() {
+    for (symbol, name) in [
+        ("_ZN3foo3barEv", Some("foo::bar()")),
+        ("_Z12signalThreadPv", Some("signalThread(void*)")),
+        (
+            "_ZN4core3ptr85drop_in_place$LT$std..rt..lang_start$LT$$LP$$RP$$GT$..$u7b$$u7b$closure$u7d$$u7d$$GT$17h4f4ed2f5cf5f2e5cE",
+            Some("core::ptr::drop_in_place<std::rt::lang_start<()>::{{closure}}>"),
+        ),
+        ("_RNvCs15kBYyAo9fc_7mycrate7example", Some("mycrate::example")),
+        ("__poll", None),
+        ("_start", None),
+        ("_Reserved", None),
+    ] {
+        assert_eq!(demangle_symbol(symbol).as_deref(), name, "{symbol}");
+    }
+
+    let demangle = Demangle::default();
+    assert_eq!(
+        demangle.line("#2  0x0000557b62997e8b _Z12signalThreadPv"),
+        "#2  0x0000557b62997e8b signalThread(void*)"
+    );
+    assert_eq!(
+        demangle.line("#3  0x00007f in _ZN3foo3barEv () at foo.cc:10"),
+        "#3  0x00007f in foo::bar() () at foo.cc:10"
+    );
+    // not at word boundary
+    assert_eq!(
+        demangle.line("#0  0x00007f x_ZN3foo3barEv"),
+        "#0  0x00007f x_ZN3foo3barEv"
+    );
+    assert_eq!(
+        Demangle::Off.line("#2  0x0000557b62997e8b _Z12signalThreadPv"),
+        "#2  0x0000557b62997e8b _Z12signalThreadPv"
+    );
+    assert_eq!(
+        Demangle::ElideTemplates.line("#1  0x00007f _ZNSt6vectorIiSaIiEE9push_backERKi"),
+        "#1  0x00007f std::vector<...>::push_back(int const&)"
+    );
+    assert_eq!(
+        Demangle::Full.stack("TID 1:\n#0  0x1 _ZN3foo3barEv\n".to_owned()),
+        "TID 1:\n#0  0x1 foo::bar()\n"
+    );
+}
+
+#[test]
+fn test_elide_templates() {
+    for (name, elided) in [
+        (
+            "std::map<int, std::vector<int, std::allocator<int> > >::find(int const&)",
+            "std::map<...>::find(int const&)",
+        ),
+        (
+            "operator<<(std::ostream&, Foo const&)",
+            "operator<<(std::ostream&, Foo const&)",
+        ),
+        (
+            "bool operator< <Foo<int> >(Foo<int> const&)",
+            "bool operator< <...>(Foo<...> const&)",
+        ),
+        ("Foo::operator->() const", "Foo::operator->() const"),
+        ("plain::function(int)", "plain::function(int)"),
+    ] {
+        assert_eq!(elide_templates(name), elided);
+    }
+}
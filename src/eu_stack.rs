@@ -3,64 +3,57 @@ use std::sync::{Arc, Mutex};
 
 use crate::{
     args::Cli,
-    uniquify::uniquify_eustack,
-    utils::{display_result, ensure_file_exists, execute_command, setup_pager},
+    filter::StackFilter,
+    format::InputFormat,
+    history::archive_samples,
+    sample::{parse_samples, report_samples, take_samples, Report, Sample},
+    stack::Thread,
+    symbolize::symbolize_samples,
+    uniquify::combine_threads,
+    utils::{display_result, ensure_file_exists, sampling_prefix, setup_pager},
+    watch::run_watch,
 };
 
-async fn do_run_eustack(
-    args: Vec<String>,
-    unique: bool,
+/// sample_eustack  -  run eu-stack `count` times, with `interval` seconds in between.
+async fn sample_eustack(
+    args: &[String],
+    pid: Option<&str>,
+    filter: &StackFilter,
     interval: Option<f32>,
     count: i32,
-) -> Result<String, String> {
-    let mut output = vec![];
-    let mut count = if interval.is_none() { 1 } else { count };
-    let sleep = interval.unwrap_or(0.0);
-    let prefix = if count == 1 {
-        "".to_owned()
-    } else {
-        format!("Interval: {}, Count: {}", sleep, count)
-    };
-
-    loop {
-        match execute_command("eu-stack", &args).await {
-            Ok((code, out, err)) => {
-                if code <= 1 {
-                    if !err.is_empty() {
-                        eprintln!("Warnings reported: {err}");
-                    }
-
-                    output.push(out);
-                } else {
-                    return Err(err);
-                }
-            }
-            Err(err) => {
-                return Err(err.to_string());
-            }
-        }
-
-        count -= 1;
-        if count == 0 {
-            break;
-        }
+) -> Result<Vec<Sample>, String> {
+    let mut samples = take_samples("eu-stack", args, pid, interval, count).await?;
+    symbolize_samples(&mut samples, &filter.symbols, &filter.maps);
+    Ok(samples)
+}
 
-        tokio::time::sleep(tokio::time::Duration::from_secs_f32(sleep)).await;
-    }
+/// sample_threads  -  threads of process `pid`, sampled once.
+pub async fn sample_threads(pid: &str, filter: &StackFilter) -> Result<Vec<Thread>, String> {
+    let args = vec!["-p".to_string(), pid.to_string()];
+    let samples = sample_eustack(&args, Some(pid), filter, None, 1).await?;
+    parse_samples(&samples, InputFormat::EuStack)
+}
 
-    let result = if unique {
-        match uniquify_eustack(&output.join("\n")) {
-            Ok(o) => format!("{}\n{}", prefix, o),
-            Err(err) => return Err(err.to_string()),
-        }
-    } else {
-        format!("{}\n{}", prefix, output.join("\n"))
-    };
+pub async fn do_run_eustack(
+    args: Vec<String>,
+    pid: Option<&str>,
+    filter: &StackFilter,
+    interval: Option<f32>,
+    count: i32,
+    report: Report,
+) -> Result<String, String> {
+    let samples = sample_eustack(&args, pid, filter, interval, count).await?;
+    let prefix = sampling_prefix(interval, count);
 
-    Ok(result)
+    let output = report_samples(samples, InputFormat::EuStack, filter, report)?;
+    Ok(format!("{}\n{}", prefix, output))
 }
 
 pub async fn run_eustack(cli: &Cli) {
+    if cli.watch {
+        run_watch(cli).await;
+    }
+
     if let Some(corefile) = &cli.core {
         let mut args = vec![];
         args.push("--core".into());
@@ -73,7 +66,16 @@ pub async fn run_eustack(cli: &Cli) {
         };
 
         setup_pager(cli);
-        match do_run_eustack(args, cli.unique_mode, None, 1).await {
+        match do_run_eustack(
+            args,
+            None,
+            &StackFilter::from_cli(cli),
+            None,
+            1,
+            Report::from_cli(cli),
+        )
+        .await
+        {
             Ok(result) => {
                 println!("{result}");
                 std::process::exit(0);
@@ -89,13 +91,19 @@ pub async fn run_eustack(cli: &Cli) {
         let mut handles = vec![];
         let outputs = Arc::new(Mutex::new(vec![]));
         let errors = Arc::new(Mutex::new(vec![]));
+        let threads: Arc<Mutex<Vec<Thread>>> = Arc::new(Mutex::new(vec![]));
 
-        let unique = cli.unique_mode;
+        let combine = cli.combine_mode;
+        // built once, reading maps, core and signature files once for all processes
+        let filter = Arc::new(StackFilter::from_cli(cli));
         for pid in pids.clone() {
             let output_ref = outputs.clone();
             let error_ref = errors.clone();
+            let thread_ref = threads.clone();
             let interval = cli.interval;
             let count = cli.count;
+            let report = Report::from_cli(cli);
+            let filter = filter.clone();
             handles.push(tokio::spawn(async move {
                 let args = vec!["-p".to_string(), pid.to_string()];
                 println!(
@@ -103,7 +111,28 @@ pub async fn run_eustack(cli: &Cli) {
                     pid,
                     std::thread::current().id()
                 );
-                match do_run_eustack(args, unique, interval, count).await {
+                if combine {
+                    let result = sample_eustack(&args, Some(&pid), &filter, interval, count)
+                        .await
+                        .and_then(|samples| {
+                            if let Some(dir) = &report.save {
+                                archive_samples(dir, &samples, InputFormat::EuStack);
+                            }
+                            parse_samples(&samples, InputFormat::EuStack)
+                        });
+                    match result {
+                        Ok(parsed) => {
+                            thread_ref.lock().unwrap().extend(parsed);
+                        }
+                        Err(err) => {
+                            eprintln!("Process {pid} returns error: {err}");
+                            error_ref.lock().unwrap().push(pid);
+                        }
+                    }
+                    return;
+                }
+
+                match do_run_eustack(args, Some(&pid), &filter, interval, count, report).await {
                     Ok(output) => {
                         output_ref.lock().unwrap().push(output);
                     }
@@ -116,6 +145,9 @@ pub async fn run_eustack(cli: &Cli) {
         }
 
         join_all(handles).await;
+        if combine {
+            combine_threads(cli, &filter, &threads.lock().unwrap(), &outputs);
+        }
         display_result(cli, errors, outputs);
     }
 
@@ -0,0 +1,311 @@
+use regex::Regex;
+use std::path::PathBuf;
+
+use crate::{
+    args::Cli,
+    demangle::Demangle,
+    signature::Signatures,
+    source::SourceView,
+    stack::{function_name, Thread},
+    symbolize::{maps_from_cli, Mapping},
+    uniquify::Simplify,
+};
+
+/// Longest cycle of frames looked for when collapsing recursions.
+const MAX_CYCLE: usize = 8;
+
+/// collapse_frames  -  collapse runs of identical or cyclic frames.
+// Frames are compared by function name. A run of one repeated frame is replaced by its first frame,
+// annotated with repeat count; a cycle of several frames (e.g. mutual recursion, `std::function`
+// trampolines) is kept once, its first frame annotated with repeat count and cycle length.
+// Frames without symbol may be anything, `??` is never equal to another frame.
+pub fn collapse_frames(frames: Vec<String>) -> Vec<String> {
+    let names: Vec<(&str, usize)> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match function_name(f) {
+            "??" => ("??", i + 1),
+            name => (name, 0),
+        })
+        .collect();
+    let mut collapsed = vec![];
+    let mut i = 0;
+    while i < frames.len() {
+        // pick cycle length covering most frames
+        let mut best = (1, 1);
+        for period in 1..=MAX_CYCLE.min((frames.len() - i) / 2) {
+            let mut repeats = 1;
+            while i + (repeats + 1) * period <= frames.len()
+                && names[i..i + period] == names[i + repeats * period..i + (repeats + 1) * period]
+            {
+                repeats += 1;
+            }
+            if repeats > 1 && repeats * period > best.0 * best.1 {
+                best = (period, repeats);
+            }
+        }
+
+        let (period, repeats) = best;
+        if repeats == 1 {
+            collapsed.push(frames[i].clone());
+        } else if period == 1 {
+            collapsed.push(format!("{}  [x {repeats}]", frames[i]));
+        } else {
+            collapsed.push(format!(
+                "{}  [x {repeats}, cycle of {period} frames]",
+                frames[i]
+            ));
+            collapsed.extend(frames[i + 1..i + period].iter().cloned());
+        }
+        i += period * repeats;
+    }
+
+    collapsed
+}
+
+/// Filters applied to each thread before it is grouped with others.
+#[derive(Clone, Debug, Default)]
+pub struct StackFilter {
+    /// If not empty, only threads whose name matches any of these are kept.
+    pub thread_names: Vec<Regex>,
+    /// If not empty, only threads having a frame matching any of these are kept.
+    pub with_frames: Vec<Regex>,
+    /// If not empty, only threads in one of these scheduler states are kept.
+    pub thread_states: Vec<char>,
+    /// Frames matching any of these are dropped.
+    pub hide_frames: Vec<Regex>,
+    /// If not empty, only frames matching any of these are kept.
+    pub only_frames: Vec<Regex>,
+    /// Collapse recursions, see `collapse_frames`.
+    pub collapse: bool,
+    /// Demangling of frames, done before any other filter.
+    pub demangle: Demangle,
+    /// Executables, libraries and debug directories to resolve frames without symbol with.
+    pub symbols: Vec<PathBuf>,
+    /// Memory map of process of stack files or core file, used to resolve frames.
+    pub maps: Vec<Mapping>,
+    /// Source shown for top frames of unique stacks.
+    pub source: Option<SourceView>,
+    /// Known issues unique stacks are matched against, and whether fingerprints are shown.
+    pub signatures: Option<Signatures>,
+    /// What is kept of frames when stacks are simplified.
+    pub simplify: Simplify,
+}
+
+impl StackFilter {
+    pub fn from_cli(cli: &Cli) -> StackFilter {
+        StackFilter {
+            thread_names: cli.thread_names.clone(),
+            with_frames: cli.with_frames.clone(),
+            thread_states: cli
+                .thread_states
+                .as_deref()
+                .unwrap_or_default()
+                .chars()
+                .collect(),
+            hide_frames: cli.hide_frames.clone(),
+            only_frames: cli.only_frames.clone(),
+            collapse: cli.collapse,
+            demangle: Demangle::from_cli(cli),
+            symbols: cli
+                .symbols
+                .iter()
+                .chain(cli.executable.iter())
+                .map(PathBuf::from)
+                .collect(),
+            maps: maps_from_cli(cli),
+            source: SourceView::from_cli(cli),
+            signatures: Signatures::from_cli(cli),
+            simplify: Simplify::from_cli(cli),
+        }
+    }
+
+    fn keep_thread(&self, thread: &Thread) -> bool {
+        // state is unknown for threads read from files, and name for threads of eu-stack, which
+        // has no thread names: such threads never match
+        (self.thread_names.is_empty()
+            || thread
+                .name
+                .as_ref()
+                .is_some_and(|name| self.thread_names.iter().any(|r| r.is_match(name))))
+            && (self.thread_states.is_empty()
+                || thread
+                    .state
+                    .is_some_and(|state| self.thread_states.contains(&state)))
+            && (self.with_frames.is_empty()
+                || thread
+                    .frames
+                    .iter()
+                    .any(|frame| self.with_frames.iter().any(|r| r.is_match(frame))))
+    }
+
+    fn keep_frame(&self, frame: &str) -> bool {
+        !self.hide_frames.iter().any(|r| r.is_match(frame))
+            && (self.only_frames.is_empty() || self.only_frames.iter().any(|r| r.is_match(frame)))
+    }
+
+    /// apply  -  filter frames of `thread`, returns None if the thread should not be shown.
+    // A thread is dropped when none of its frames are kept, otherwise every thread would end up in
+    // a group of empty stack when `only_frames` is used.
+    pub fn apply(&self, mut thread: Thread) -> Option<Thread> {
+        if self.demangle != Demangle::Off {
+            for frame in thread.frames.iter_mut() {
+                *frame = self.demangle.line(frame);
+            }
+        }
+
+        if !self.keep_thread(&thread) {
+            return None;
+        }
+
+        if self.collapse {
+            thread.frames = collapse_frames(std::mem::take(&mut thread.frames));
+        }
+
+        if self.hide_frames.is_empty() && self.only_frames.is_empty() {
+            return Some(thread);
+        }
+
+        let had_frames = !thread.frames.is_empty();
+        thread.frames.retain(|frame| self.keep_frame(frame));
+        if had_frames && thread.frames.is_empty() {
+            None
+        } else {
+            Some(thread)
+        }
+    }
+}
+
+#[test]
+fn test_frame_filter() {
+    let mut thread = Thread::new(None, None, "100");
+    for frame in [
+        "#0  0x00007f29ce8db9e7 __GI___clock_nanosleep",
+        "#1  0x00007f29ce8e6a47 __GI___nanosleep",
+        "#2  0x000055723be89162 myapp::worker::run",
+        "#3  0x00007f29ce8f7bce start_thread",
+        "#4  0x00007f29ce83f320 libpthread.so.0",
+    ] {
+        thread.frames.push(frame.to_owned());
+    }
+
+    let filter = StackFilter::default();
+    assert_eq!(filter.apply(thread.clone()), Some(thread.clone()));
+
+    let filter = StackFilter {
+        hide_frames: vec![
+            Regex::new("__GI_").unwrap(),
+            Regex::new("libpthread").unwrap(),
+        ],
+        ..Default::default()
+    };
+    let frames = filter.apply(thread.clone()).unwrap().frames;
+    assert_eq!(frames.len(), 2);
+    assert!(frames[0].ends_with("myapp::worker::run"));
+    assert!(frames[1].ends_with("start_thread"));
+
+    let filter = StackFilter {
+        only_frames: vec![Regex::new("myapp::").unwrap()],
+        ..Default::default()
+    };
+    let frames = filter.apply(thread.clone()).unwrap().frames;
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].ends_with("myapp::worker::run"));
+
+    let filter = StackFilter {
+        hide_frames: vec![Regex::new("worker").unwrap()],
+        only_frames: vec![Regex::new("myapp::").unwrap()],
+        ..Default::default()
+    };
+    assert!(filter.apply(thread).is_none());
+}
+
+#[test]
+fn test_thread_filter() {
+    let mut thread = Thread::new(None, Some("100"), "101");
+    thread
+        .frames
+        .push("#0  0x00007f29ce8db9e7 __lll_lock_wait".to_owned());
+    thread
+        .frames
+        .push("#1  0x000055723be89162 storage::flush".to_owned());
+
+    let filter = StackFilter {
+        thread_names: vec![Regex::new("^worker").unwrap()],
+        ..Default::default()
+    };
+    // name is unknown
+    assert!(filter.apply(thread.clone()).is_none());
+    thread.name = Some("worker-3".to_owned());
+    assert!(filter.apply(thread.clone()).is_some());
+
+    let filter = StackFilter {
+        thread_states: vec!['R', 'D'],
+        ..Default::default()
+    };
+    assert!(filter.apply(thread.clone()).is_none());
+    thread.state = Some('D');
+    assert!(filter.apply(thread.clone()).is_some());
+
+    let filter = StackFilter {
+        with_frames: vec![Regex::new("storage::").unwrap()],
+        hide_frames: vec![Regex::new("storage::").unwrap()],
+        ..Default::default()
+    };
+    // frames are checked before they are hidden
+    assert_eq!(filter.apply(thread.clone()).unwrap().frames.len(), 1);
+
+    let filter = StackFilter {
+        with_frames: vec![Regex::new("network::").unwrap()],
+        ..Default::default()
+    };
+    assert!(filter.apply(thread.clone()).is_none());
+
+    // frames are matched after demangling
+    thread
+        .frames
+        .push("#2  0x000055723be89170 _ZN7network4pollEv".to_owned());
+    assert!(filter.apply(thread.clone()).is_some());
+    let filter = StackFilter {
+        with_frames: vec![Regex::new("network::").unwrap()],
+        demangle: Demangle::Off,
+        ..Default::default()
+    };
+    assert!(filter.apply(thread).is_none());
+}
+
+#[test]
+fn test_collapse_frames() {
+    let frames = |names: &[&str]| -> Vec<String> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("#{i:<2} 0x000055723be89162 in {name} (p=0x1) at p.c:{i}"))
+            .collect()
+    };
+
+    let collapsed = collapse_frames(frames(&[
+        "lex", "parse", "parse", "parse", "parse", "main", "_start",
+    ]));
+    assert_eq!(collapsed.len(), 4);
+    assert!(collapsed[1].starts_with("#1  "));
+    assert!(collapsed[1].ends_with("in parse (p=0x1) at p.c:1  [x 4]"));
+    assert!(collapsed[2].contains("main"));
+
+    let collapsed = collapse_frames(frames(&[
+        "poll", "poll_fn", "map", "poll_fn", "map", "poll_fn", "map", "block_on",
+    ]));
+    assert_eq!(collapsed.len(), 4);
+    assert!(collapsed[1].ends_with("[x 3, cycle of 2 frames]"));
+    assert!(collapsed[2].contains("map"));
+    assert!(collapsed[3].contains("block_on"));
+
+    let unchanged = frames(&["a", "b", "c", "a", "b"]);
+    assert_eq!(collapse_frames(unchanged.clone()), unchanged);
+    // frames without symbol are not a recursion
+    let unknown = frames(&["??", "??", "??", "a", "??", "a", "??"]);
+    assert_eq!(collapse_frames(unknown.clone()), unknown);
+    let unknown = vec!["??".to_owned(), "??".to_owned()];
+    assert_eq!(collapse_frames(unknown.clone()), unknown);
+    assert!(collapse_frames(vec![]).is_empty());
+}
@@ -0,0 +1,191 @@
+use regex::Regex;
+
+use crate::{patterns, stack::Thread, uniquify::StackParser};
+
+/// Known formats of call stack dumps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    // output of `eu-stack`
+    #[value(name = "eu-stack", alias = "eustack")]
+    EuStack,
+
+    // output of gdb `thread apply all backtrace`
+    Gdb,
+}
+
+impl InputFormat {
+    /// All formats, in the order they are tried when detecting.
+    pub const ALL: [InputFormat; 2] = [InputFormat::EuStack, InputFormat::Gdb];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputFormat::EuStack => "eu-stack",
+            InputFormat::Gdb => "gdb",
+        }
+    }
+
+    pub fn parse(&self, input: &str, source: Option<&str>) -> Result<Vec<Thread>, String> {
+        self.parse_with(input, source, false)
+    }
+
+    /// parse_with  -  parse threads of `input`, with locals of gdb `bt full` if `locals`.
+    pub fn parse_with(
+        &self,
+        input: &str,
+        source: Option<&str>,
+        locals: bool,
+    ) -> Result<Vec<Thread>, String> {
+        let mut parser = StackParser::new(*self, source, locals);
+        let mut threads = vec![];
+        for s in input.split('\n') {
+            if let Some(thread) = parser.feed(s) {
+                threads.push(thread);
+            }
+        }
+        threads.extend(parser.finish());
+
+        if threads.is_empty() {
+            Err(format!("not generated by {}", self.name()))
+        } else {
+            Ok(threads)
+        }
+    }
+}
+
+/// Matchers of thread headers and frames, one pair for each of `InputFormat::ALL`.
+fn format_matchers() -> Vec<(&'static Regex, &'static Regex)> {
+    vec![
+        (
+            patterns::detect_eustack_header(),
+            patterns::detect_eustack_entry(),
+        ),
+        (patterns::gdb_tid(), patterns::detect_gdb_entry()),
+    ]
+}
+
+/// Detects input format incrementally, for input that is read line by line.
+pub struct FormatDetector {
+    matchers: Vec<(&'static Regex, &'static Regex)>,
+    headers: Vec<usize>,
+    entries: Vec<usize>,
+}
+
+impl Default for FormatDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FormatDetector {
+    pub fn new() -> FormatDetector {
+        FormatDetector {
+            matchers: format_matchers(),
+            headers: vec![0; InputFormat::ALL.len()],
+            entries: vec![0; InputFormat::ALL.len()],
+        }
+    }
+
+    /// feed  -  count line `s`, returns true if it is a thread header of any format.
+    pub fn feed(&mut self, s: &str) -> bool {
+        let mut is_header = false;
+        for (i, (r_match_header, r_match_entry)) in self.matchers.iter().enumerate() {
+            if r_match_header.is_match(s) {
+                self.headers[i] += 1;
+                is_header = true;
+            } else if r_match_entry.is_match(s) {
+                self.entries[i] += 1;
+            }
+        }
+        is_header
+    }
+
+    pub fn score(&self, format: InputFormat) -> usize {
+        let i = InputFormat::ALL.iter().position(|f| *f == format).unwrap();
+        if self.headers[i] == 0 {
+            0
+        } else {
+            self.headers[i] + self.entries[i]
+        }
+    }
+
+    /// detect  -  pick the format that matches lines fed so far best.
+    pub fn detect(&self) -> Result<InputFormat, String> {
+        match InputFormat::ALL
+            .iter()
+            .map(|f| (*f, self.score(*f)))
+            .filter(|(_, score)| *score > 0)
+            .max_by_key(|(_, score)| *score)
+        {
+            Some((format, _)) => Ok(format),
+            None => Err(format!(
+                "unrecognized input format, tried: {}",
+                InputFormat::ALL
+                    .iter()
+                    .map(|f| f.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_detect_format() {
+    let detect_format = |input: &str| {
+        let mut detector = FormatDetector::new();
+        for s in input.lines() {
+            detector.feed(s);
+        }
+        (
+            detector.detect(),
+            detector.score(InputFormat::EuStack),
+            detector.score(InputFormat::Gdb),
+        )
+    };
+
+    let input = r#"
+PID 14794 - process
+TID 14794:
+#0  0x00007f83df80a3ec g_type_check_instance_is_a
+#1  0x00007f83df14f421 gdk_frame_clock_request_phase
+#19 0x00007f83ddb902e0
+TID 14818:
+#0  0x00007f83ddba6fea __sigtimedwait
+#1  0x00007f83ddba666c sigwait
+"#;
+    let (format, _, gdb_score) = detect_format(input);
+    assert_eq!(format, Ok(InputFormat::EuStack));
+    assert_eq!(gdb_score, 0);
+
+    let input = r#"
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "test"):
+#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6
+#1  0x00007f29ce8e6a47 in nanosleep () from /usr/lib64/libc.so.6
+#9  0x000055723be89085 in _start ()
+
+Thread 1 (Thread 0x7f29ce816740 (LWP 37747) "test"):
+#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6
+#5  0x000055723be8918d in func () at test.c:15
+"#;
+    let (format, eustack_score, _) = detect_format(input);
+    assert_eq!(format, Ok(InputFormat::Gdb));
+    assert_eq!(eustack_score, 0);
+
+    // simplified gdb output still looks like gdb
+    let input = r#"
+Thread 1 (Thread 0x7f29ce816740 (LWP 37747) "test"):
+#0  0x00007f29ce8db9e7 clock_nanosleep
+#1  0x00007f29ce8e6a47 nanosleep
+"#;
+    assert_eq!(detect_format(input).0, Ok(InputFormat::Gdb));
+
+    match detect_format("some random text\n#0 foo").0 {
+        Ok(_) => panic!(),
+        Err(err) => {
+            assert!(err.contains("eu-stack"));
+            assert!(err.contains("gdb"));
+        }
+    }
+
+    assert!(InputFormat::Gdb.parse("some random text", None).is_err());
+}
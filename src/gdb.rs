@@ -3,63 +3,75 @@ use std::sync::{Arc, Mutex};
 
 use crate::{
     args::Cli,
-    uniquify::{simplify_stack, uniquify_gdb},
-    utils::{display_result, execute_command},
+    filter::StackFilter,
+    format::InputFormat,
+    history::archive_samples,
+    locals::run_gdb_locals,
+    mi::capture,
+    sample::{parse_samples, report_samples, take_samples_with, Report, Sample},
+    stack::Thread,
+    symbolize::symbolize_samples,
+    uniquify::{combine_threads, simplify_line, simplify_stack},
+    utils::{display_result, sampling_prefix},
+    watch::run_watch,
 };
 
-async fn do_run_gdb(
-    args: Vec<&str>,
-    unique: bool,
+/// sample_gdb  -  capture stacks through GDB/MI `count` times, with `interval` seconds in between.
+// Arguments of frames are listed in raw mode only, they are dropped by simplification otherwise.
+async fn sample_gdb(
+    pid: &str,
     raw: bool,
+    filter: &StackFilter,
     interval: Option<f32>,
     count: i32,
-) -> Result<String, String> {
-    let mut output = vec![];
-    let mut count = if interval.is_none() { 1 } else { count };
-    let sleep = interval.unwrap_or(0.0);
-    let prefix = if count == 1 {
-        "".to_owned()
-    } else {
-        format!("Interval: {}, Count: {}", sleep, count)
-    };
-
-    loop {
-        match execute_command("gdb", &args).await {
-            Ok((code, out, err)) => {
-                if code <= 1 {
-                    if !err.is_empty() {
-                        eprintln!("Warnings reported: {err}");
-                    }
-
-                    let out = if raw { out } else { simplify_stack(out) };
-                    output.push(out);
-                } else {
-                    return Err(err);
-                }
+) -> Result<Vec<Sample>, String> {
+    let mut samples = take_samples_with(Some(pid), interval, count, || capture(pid, raw)).await?;
+    symbolize_samples(&mut samples, &filter.symbols, &filter.maps);
+    if !raw {
+        for sample in samples.iter_mut() {
+            sample.output = simplify_stack(std::mem::take(&mut sample.output), &filter.simplify);
+            for frame in sample.records.iter_mut().flat_map(|t| t.frames.iter_mut()) {
+                *frame = simplify_line(frame, &filter.simplify);
             }
-            Err(err) => return Err(err.to_string()),
-        }
-        count -= 1;
-        if count == 0 {
-            break;
         }
-
-        tokio::time::sleep(tokio::time::Duration::from_secs_f32(sleep)).await;
     }
+    Ok(samples)
+}
 
-    let result = if unique {
-        match uniquify_gdb(&output.join("\n")) {
-            Ok(o) => format!("{}\n{}", prefix, o),
-            Err(err) => return Err(err.to_string()),
-        }
-    } else {
-        format!("{}\n{}", prefix, output.join("\n"))
-    };
+/// sample_threads  -  threads of process `pid`, sampled once.
+pub async fn sample_threads(
+    pid: &str,
+    raw: bool,
+    filter: &StackFilter,
+) -> Result<Vec<Thread>, String> {
+    let samples = sample_gdb(pid, raw, filter, None, 1).await?;
+    parse_samples(&samples, InputFormat::Gdb)
+}
+
+pub async fn do_run_gdb(
+    pid: &str,
+    filter: &StackFilter,
+    raw: bool,
+    interval: Option<f32>,
+    count: i32,
+    report: Report,
+) -> Result<String, String> {
+    let samples = sample_gdb(pid, raw, filter, interval, count).await?;
+    let prefix = sampling_prefix(interval, count);
 
-    Ok(result)
+    let output = report_samples(samples, InputFormat::Gdb, filter, report)?;
+    Ok(format!("{}\n{}", prefix, output))
 }
 
 pub async fn run_gdb(cli: &Cli) {
+    if cli.locals.is_some() {
+        run_gdb_locals(cli).await;
+    }
+
+    if cli.watch {
+        run_watch(cli).await;
+    }
+
     if let Some(_corefile) = &cli.core {
         panic!("not impl");
     }
@@ -68,28 +80,48 @@ pub async fn run_gdb(cli: &Cli) {
         let mut handles = vec![];
         let outputs = Arc::new(Mutex::new(vec![]));
         let errors = Arc::new(Mutex::new(vec![]));
+        let threads: Arc<Mutex<Vec<Thread>>> = Arc::new(Mutex::new(vec![]));
 
-        let unique = cli.unique_mode;
+        let combine = cli.combine_mode;
+        // built once, reading maps, core and signature files once for all processes
+        let filter = Arc::new(StackFilter::from_cli(cli));
         let raw = cli.raw_mode;
         for pid in pids.clone() {
             let output_ref = outputs.clone();
             let error_ref = errors.clone();
+            let thread_ref = threads.clone();
             let interval = cli.interval;
             let count = cli.count;
+            let report = Report::from_cli(cli);
+            let filter = filter.clone();
             handles.push(tokio::spawn(async move {
-                let args = vec![
-                    "--batch",
-                    "-p",
-                    pid.as_str(),
-                    "-ex",
-                    "thread apply all backtrace",
-                ];
                 println!(
                     "Run for process: {:?} in thread: {:?}",
                     pid,
                     std::thread::current().id()
                 );
-                match do_run_gdb(args, unique, raw, interval, count).await {
+                if combine {
+                    let result = sample_gdb(&pid, raw, &filter, interval, count)
+                        .await
+                        .and_then(|samples| {
+                            if let Some(dir) = &report.save {
+                                archive_samples(dir, &samples, InputFormat::Gdb);
+                            }
+                            parse_samples(&samples, InputFormat::Gdb)
+                        });
+                    match result {
+                        Ok(parsed) => {
+                            thread_ref.lock().unwrap().extend(parsed);
+                        }
+                        Err(err) => {
+                            eprintln!("Process {pid} returns error: {err}");
+                            error_ref.lock().unwrap().push(pid);
+                        }
+                    }
+                    return;
+                }
+
+                match do_run_gdb(&pid, &filter, raw, interval, count, report).await {
                     Ok(output) => {
                         output_ref.lock().unwrap().push(output);
                     }
@@ -102,6 +134,9 @@ pub async fn run_gdb(cli: &Cli) {
         }
 
         join_all(handles).await;
+        if combine {
+            combine_threads(cli, &filter, &threads.lock().unwrap(), &outputs);
+        }
         display_result(cli, errors, outputs);
     }
 
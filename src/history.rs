@@ -0,0 +1,357 @@
+// Snapshot archive: stacks captured are saved along with what is needed to understand them later,
+// and listed or shown again by `cs history`.
+// Each snapshot is a directory holding output of the backend for each sample (`sample-N.txt`),
+// threads parsed from them (`threads.json`) and metadata (`snapshot.json`).
+
+use pager::Pager;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    args::Cli,
+    filter::StackFilter,
+    format::InputFormat,
+    locks::print_deadlocks,
+    sample::Sample,
+    stack::Thread,
+    symbolize::Symbolizer,
+    tui::browse,
+    uniquify::{print_unique, simplify_line, uniquify_stack_files, StackGroups},
+    utils::format_date_time,
+};
+
+/// Metadata of a snapshot.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub host: String,
+    /// Process captured, None for core files.
+    pub pid: Option<String>,
+    pub cmdline: String,
+    /// Time the first sample is taken at, in milliseconds since epoch.
+    pub time: u64,
+    /// Format of output of the backend, as named by --input-format.
+    pub backend: String,
+    pub samples: usize,
+    pub threads: usize,
+}
+
+/// history_dir  -  directory of the archive given to --save, `~/.local/share/cs/history` if empty.
+pub fn history_dir(dir: &str) -> PathBuf {
+    if !dir.is_empty() {
+        return PathBuf::from(dir);
+    }
+    let data = std::env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(".local/share")
+        });
+    data.join("cs").join("history")
+}
+
+/// save_snapshot  -  save `samples` of a process in a new snapshot of archive `dir`.
+// Host and command line are read now, while the process is still there. Output of the backend is
+// saved as is, frames are resolved and simplified again when the snapshot is shown.
+pub fn save_snapshot(
+    dir: &Path,
+    samples: &[Sample],
+    format: InputFormat,
+) -> Result<PathBuf, String> {
+    let samples: Vec<Sample> = samples
+        .iter()
+        .map(|s| Sample {
+            output: s.raw.clone(),
+            records: s.raw_records.clone(),
+            ..s.clone()
+        })
+        .collect();
+    let mut threads: Vec<Thread> = vec![];
+    for sample in samples.iter() {
+        threads.extend(sample.threads(format).unwrap_or_default());
+    }
+
+    let pid = samples.first().and_then(|s| s.pid.clone());
+    let time = samples
+        .first()
+        .and_then(|s| s.time)
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let read = |path: String| std::fs::read_to_string(path).unwrap_or_default();
+    let snapshot = Snapshot {
+        host: read("/proc/sys/kernel/hostname".to_owned())
+            .trim()
+            .to_owned(),
+        cmdline: pid
+            .as_ref()
+            .map(|pid| read(format!("/proc/{pid}/cmdline")))
+            .unwrap_or_default()
+            .trim_end_matches('\0')
+            .replace('\0', " "),
+        pid,
+        time,
+        backend: format.name().to_owned(),
+        samples: samples.len(),
+        threads: threads.len(),
+    };
+
+    let path = dir.join(format!(
+        "{time}-{}",
+        snapshot.pid.as_deref().unwrap_or("core")
+    ));
+    let write = |name: &str, content: &str| {
+        std::fs::write(path.join(name), content)
+            .map_err(|err| format!("failed to write {}: {err}", path.join(name).display()))
+    };
+    std::fs::create_dir_all(&path)
+        .map_err(|err| format!("failed to create {}: {err}", path.display()))?;
+    for (i, sample) in samples.iter().enumerate() {
+        write(&format!("sample-{}.txt", i + 1), &sample.output)?;
+    }
+    write(
+        "threads.json",
+        &serde_json::to_string_pretty(&threads).map_err(|err| err.to_string())?,
+    )?;
+    write(
+        "snapshot.json",
+        &serde_json::to_string_pretty(&snapshot).map_err(|err| err.to_string())?,
+    )?;
+    Ok(path)
+}
+
+/// archive_samples  -  save `samples` in archive `dir`, telling where.
+// Failing to save is reported, stacks are shown anyway.
+pub fn archive_samples(dir: &Path, samples: &[Sample], format: InputFormat) {
+    match save_snapshot(dir, samples, format) {
+        Ok(path) => println!("Saved snapshot: {}", path.display()),
+        Err(err) => eprintln!("Failed to save snapshot: {err}"),
+    }
+}
+
+/// list_snapshots  -  snapshots of archive `dir`, latest first.
+// Directories without readable metadata are skipped, an archive not created yet is empty.
+pub fn list_snapshots(dir: &Path) -> Vec<(PathBuf, Snapshot)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut snapshots: Vec<(PathBuf, Snapshot)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let content = std::fs::read_to_string(entry.path().join("snapshot.json")).ok()?;
+            Some((entry.path(), serde_json::from_str(&content).ok()?))
+        })
+        .collect();
+    snapshots.sort_by(|(p1, s1), (p2, s2)| (s2.time, p2).cmp(&(s1.time, p1)));
+    snapshots
+}
+
+/// print_snapshots  -  table of `snapshots`, numbered as given to `cs history`.
+pub fn print_snapshots(snapshots: &[(PathBuf, Snapshot)]) -> String {
+    let mut output =
+        "   #  Time (UTC)           Host             PID       Backend   Samples  Threads  Command\n"
+            .to_owned();
+    for (i, (_, s)) in snapshots.iter().enumerate() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(s.time);
+        output.push_str(&format!(
+            "{:>4}  {}  {:<15}  {:<8}  {:<8}  {:>7}  {:>7}  {}\n",
+            i + 1,
+            format_date_time(time),
+            s.host,
+            s.pid.as_deref().unwrap_or("-"),
+            s.backend,
+            s.samples,
+            s.threads,
+            s.cmdline
+        ));
+    }
+    output
+}
+
+/// find_snapshot  -  snapshot `id` of `snapshots`, by number in listing or by directory name.
+pub fn find_snapshot<'a>(
+    snapshots: &'a [(PathBuf, Snapshot)],
+    id: &str,
+) -> Option<&'a (PathBuf, Snapshot)> {
+    if let Ok(n) = id.parse::<usize>() {
+        if n >= 1 && n <= snapshots.len() {
+            return Some(&snapshots[n - 1]);
+        }
+    }
+    snapshots
+        .iter()
+        .find(|(path, _)| path.file_name().is_some_and(|name| name == id))
+}
+
+/// load_threads  -  threads of snapshot `path`, with frames resolved and simplified as shown.
+// Threads are saved along with process, state and lock owner, which output of the backend lacks,
+// frames as the backend gives them, thus they are resolved and simplified again like samples.
+pub fn load_threads(path: &Path, filter: &StackFilter, raw: bool) -> Result<Vec<Thread>, String> {
+    let file = path.join("threads.json");
+    let mut threads: Vec<Thread> = std::fs::read_to_string(&file)
+        .map_err(|err| err.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|err| err.to_string()))
+        .map_err(|err| format!("failed to read {}: {err}", file.display()))?;
+
+    let mut symbolizer = (!filter.symbols.is_empty()).then(|| Symbolizer::new(&filter.symbols));
+    for frame in threads.iter_mut().flat_map(|t| t.frames.iter_mut()) {
+        if let Some(symbolizer) = symbolizer.as_mut() {
+            *frame = symbolizer.line(&filter.maps, frame);
+        }
+        if !raw {
+            *frame = simplify_line(frame, &filter.simplify);
+        }
+    }
+    Ok(threads)
+}
+
+/// run_history  -  list snapshots of the archive, or show snapshot `id`.
+// Samples are shown from output of the backend saved, unique stacks from threads saved.
+pub async fn run_history(mut cli: Cli, id: Option<String>) {
+    let dir = history_dir(cli.save.as_deref().unwrap_or_default());
+    let snapshots = list_snapshots(&dir);
+    let Some(id) = id else {
+        if snapshots.is_empty() {
+            println!("No snapshot in {}.", dir.display());
+        } else {
+            print!("{}", print_snapshots(&snapshots));
+        }
+        exit(0);
+    };
+
+    let Some((path, snapshot)) = find_snapshot(&snapshots, &id) else {
+        eprintln!("No snapshot {id} in {}.", dir.display());
+        exit(2);
+    };
+    if cli.unique_mode || cli.tui {
+        show_threads(&cli, path);
+        exit(0);
+    }
+
+    cli.files = (1..=snapshot.samples)
+        .map(|i| {
+            path.join(format!("sample-{i}.txt"))
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    if cli.input_format.is_none() {
+        cli.input_format = InputFormat::ALL
+            .into_iter()
+            .find(|f| f.name() == snapshot.backend);
+    }
+    cli.save = None;
+    uniquify_stack_files(cli).await;
+}
+
+/// show_threads  -  show unique stacks of threads of snapshot `path`, or browse them.
+fn show_threads(cli: &Cli, path: &Path) {
+    let filter = StackFilter::from_cli(cli);
+    let threads = load_threads(path, &filter, cli.raw_mode).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        exit(2);
+    });
+
+    if cli.tui {
+        let (groups, notes) = StackGroups::from_threads(&threads, &filter).into_noted_groups();
+        if let Err(err) = browse(groups, notes) {
+            eprintln!("Failed to browse stacks: {err}");
+            exit(2);
+        }
+        return;
+    }
+
+    Pager::new().setup();
+    match print_unique(StackGroups::from_threads(&threads, &filter)) {
+        Ok(result) => println!("{result}{}", print_deadlocks(&threads)),
+        Err(err) => {
+            eprintln!("Failed to handle content: {err}");
+            exit(2);
+        }
+    }
+}
+
+#[test]
+fn test_snapshots() {
+    use crate::procfs::TaskStat;
+
+    let dir = std::env::temp_dir().join(format!("cs-history-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(list_snapshots(&dir).is_empty());
+
+    let sample = |secs: u64, pid: &str| Sample {
+        pid: Some(pid.to_owned()),
+        output: "Thread 1 (Thread 0x7f29ce816740 (LWP 37746) \"main\"):\n\
+                 #0  0x00007f29ce8db9e7 __poll\n"
+            .to_owned(),
+        raw: "Thread 1 (Thread 0x7f29ce816740 (LWP 37746) \"main\"):\n\
+              #0  0x00007f29ce8db9e7 in __poll () from /usr/lib64/libc.so.6\n"
+            .to_owned(),
+        tasks: [(
+            "37746".to_owned(),
+            TaskStat {
+                state: 'S',
+                ..Default::default()
+            },
+        )]
+        .into(),
+        time: Some(UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+        ..Default::default()
+    };
+    let me = std::process::id().to_string();
+    let path = save_snapshot(
+        &dir,
+        &[sample(1000, &me), sample(1001, &me)],
+        InputFormat::Gdb,
+    )
+    .unwrap();
+    assert_eq!(path, dir.join(format!("1000000-{me}")));
+    // output of the backend as is, not as shown
+    let output = std::fs::read_to_string(path.join("sample-2.txt")).unwrap();
+    assert!(output.contains("in __poll () from /usr/lib64/libc.so.6"));
+    let threads = std::fs::read_to_string(path.join("threads.json")).unwrap();
+    assert!(threads.contains("\"tid\": \"37746\""));
+    // process and state are read back, frames simplified again unless raw
+    let threads = load_threads(&path, &StackFilter::default(), false).unwrap();
+    assert_eq!(threads.len(), 2);
+    assert_eq!(threads[0].pid.as_deref(), Some(me.as_str()));
+    assert_eq!(threads[0].state, Some('S'));
+    assert_eq!(threads[0].frames, vec!["#0  0x00007f29ce8db9e7 __poll"]);
+    let threads = load_threads(&path, &StackFilter::default(), true).unwrap();
+    assert!(threads[0].frames[0].ends_with("in __poll () from /usr/lib64/libc.so.6"));
+    assert!(load_threads(&dir, &StackFilter::default(), false).is_err());
+    save_snapshot(&dir, &[sample(2000, "1")], InputFormat::EuStack).unwrap();
+
+    let snapshots = list_snapshots(&dir);
+    assert_eq!(snapshots.len(), 2);
+    let (_, latest) = &snapshots[0];
+    assert_eq!(latest.time, 2000000);
+    assert_eq!(latest.backend, "eu-stack");
+    let (_, first) = &snapshots[1];
+    assert_eq!(first.pid.as_deref(), Some(me.as_str()));
+    assert_eq!((first.samples, first.threads), (2, 2));
+    assert!(!first.cmdline.is_empty());
+
+    assert_eq!(find_snapshot(&snapshots, "2").unwrap().0, path);
+    assert_eq!(
+        find_snapshot(&snapshots, &format!("1000000-{me}"))
+            .unwrap()
+            .0,
+        path
+    );
+    assert!(find_snapshot(&snapshots, "3").is_none());
+
+    let output = print_snapshots(&snapshots);
+    assert_eq!(output.lines().count(), 3);
+    assert!(output
+        .lines()
+        .nth(2)
+        .unwrap()
+        .starts_with("   2  1970-01-01 00:16:40  "));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
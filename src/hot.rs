@@ -0,0 +1,126 @@
+// Hot functions, computed from stacks of all threads of all samples, as `perf report` does.
+// Each thread stack of each sample counts as one sample: the function at its top is running
+// (exclusive), every function in it is on the call stack (inclusive).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    filter::StackFilter,
+    stack::{function_name, Thread},
+};
+
+/// Samples of functions.
+#[derive(Debug, Default)]
+pub struct HotFrames {
+    /// Number of thread stacks sampled.
+    pub total: usize,
+    inclusive: HashMap<String, usize>,
+    exclusive: HashMap<String, usize>,
+}
+
+impl HotFrames {
+    /// from_threads  -  count functions of `threads` passing `filter`.
+    pub fn from_threads(threads: &[Thread], filter: &StackFilter) -> HotFrames {
+        let mut hot = HotFrames::default();
+        for thread in threads {
+            if let Some(thread) = filter.apply(thread.clone()) {
+                hot.add(&thread);
+            }
+        }
+        hot
+    }
+
+    pub fn add(&mut self, thread: &Thread) {
+        let Some(top) = thread.frames.first() else {
+            return;
+        };
+
+        self.total += 1;
+        *self
+            .exclusive
+            .entry(function_name(top).to_owned())
+            .or_default() += 1;
+        // recursive functions are counted once per stack
+        let functions: HashSet<&str> = thread.frames.iter().map(|f| function_name(f)).collect();
+        for function in functions {
+            *self.inclusive.entry(function.to_owned()).or_default() += 1;
+        }
+    }
+
+    /// ranked  -  (function, inclusive, exclusive) of `limit` hottest functions.
+    // Functions are ranked by samples they run in first, then by samples they are called in.
+    pub fn ranked(&self, limit: usize) -> Vec<(&str, usize, usize)> {
+        let mut rows: Vec<(&str, usize, usize)> = self
+            .inclusive
+            .iter()
+            .map(|(name, n)| {
+                let exclusive = self.exclusive.get(name).copied().unwrap_or(0);
+                (name.as_str(), *n, exclusive)
+            })
+            .collect();
+        rows.sort_by(|(n1, i1, e1), (n2, i2, e2)| (e2, i2).cmp(&(e1, i1)).then(n1.cmp(n2)));
+        rows.truncate(limit);
+        rows
+    }
+
+    /// print  -  table of `limit` hottest functions, with percentages of samples.
+    pub fn print(&self, limit: usize) -> String {
+        if self.total == 0 {
+            return "Hot functions: no samples".to_owned();
+        }
+
+        let percent = |n: usize| n as f64 * 100.0 / self.total as f64;
+        let mut lines = vec![
+            format!("Hot functions of {} thread samples:", self.total),
+            "  Inclusive   Exclusive  Function".to_owned(),
+        ];
+        for (name, inclusive, exclusive) in self.ranked(limit) {
+            lines.push(format!(
+                "  {:>8.2}%   {:>8.2}%  {name}",
+                percent(inclusive),
+                percent(exclusive)
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[test]
+fn test_hot_frames() {
+    let thread = |frames: &[&str]| {
+        let mut thread = Thread::new(None, None, "1");
+        thread.frames = frames.iter().map(|s| s.to_string()).collect();
+        thread
+    };
+    let threads = vec![
+        thread(&["#0  0x1 compute", "#1  0x2 work", "#2  0x3 main"]),
+        thread(&["#0  0x1 compute", "#1  0x2 work", "#2  0x3 main"]),
+        thread(&["#0  0x4 __poll", "#1  0x3 main"]),
+        // recursion counts once
+        thread(&[
+            "#0  0x5 walk",
+            "#1  0x5 walk",
+            "#2  0x5 walk",
+            "#3  0x3 main",
+        ]),
+        thread(&[]),
+    ];
+    let hot = HotFrames::from_threads(&threads, &StackFilter::default());
+    assert_eq!(hot.total, 4);
+    assert_eq!(
+        hot.ranked(3),
+        vec![("compute", 2, 2), ("__poll", 1, 1), ("walk", 1, 1)]
+    );
+    assert_eq!(hot.ranked(10).len(), 5);
+    assert_eq!(hot.ranked(10)[3], ("main", 4, 0));
+
+    let output = hot.print(2);
+    assert_eq!(
+        output,
+        "Hot functions of 4 thread samples:\n  \
+         Inclusive   Exclusive  Function\n     \
+         50.00%      50.00%  compute\n     \
+         25.00%      25.00%  __poll"
+    );
+    assert_eq!(HotFrames::default().print(10), "Hot functions: no samples");
+}
@@ -0,0 +1,28 @@
+pub mod utils;
+
+pub mod args;
+pub mod classify;
+pub mod crash;
+pub mod demangle;
+pub mod eu_stack;
+pub mod filter;
+pub mod format;
+pub mod gdb;
+pub mod history;
+pub mod hot;
+pub mod locals;
+pub mod locks;
+pub mod mi;
+pub mod patterns;
+pub mod procfs;
+pub mod sample;
+pub mod signature;
+pub mod source;
+pub mod stack;
+pub mod stuck;
+pub mod symbolize;
+pub mod timeline;
+pub mod trigger;
+pub mod tui;
+pub mod uniquify;
+pub mod watch;
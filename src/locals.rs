@@ -0,0 +1,257 @@
+// Arguments and local variables of frames, captured with gdb `bt full`.
+// Values are kept per thread, but shown only for stacks chosen by user, as they are verbose.
+
+use inquire::Select;
+use std::process::exit;
+
+use crate::{
+    args::Cli,
+    filter::StackFilter,
+    format::InputFormat,
+    patterns,
+    sample::take_samples,
+    stack::Thread,
+    uniquify::{print_stacks, simplify_line, sort_groups, Notes, StackGroups},
+};
+
+/// split_arguments  -  split `s` at commas that are not nested in brackets or quotes.
+fn split_arguments(s: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// frame_arguments  -  arguments of gdb frame `frame`, as (name, value) pairs.
+pub fn frame_arguments(frame: &str) -> Vec<(String, String)> {
+    let Some(m) = patterns::gdb_frame_arguments().captures(frame) else {
+        return vec![];
+    };
+
+    split_arguments(m.name("args").unwrap().as_str())
+        .into_iter()
+        .filter_map(|arg| arg.split_once('='))
+        .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+        .collect()
+}
+
+/// prepare_thread  -  move arguments of frames of `thread` into its variables, then simplify frames.
+// Frames keep arguments until they are parsed, values would make every stack unique otherwise.
+fn prepare_thread(thread: &mut Thread, filter: &StackFilter, raw: bool) {
+    for frame in thread.frames.iter_mut() {
+        let args = frame_arguments(frame);
+        if !args.is_empty() {
+            if let Some(level) = patterns::frame_function()
+                .captures(frame)
+                .and_then(|m| m.name("level").unwrap().as_str().parse().ok())
+            {
+                thread.vars.entry(level).or_default().args = args;
+            }
+        }
+        if !raw {
+            *frame = simplify_line(frame, &filter.simplify);
+        }
+    }
+}
+
+/// print_vars  -  print stack of a group, with arguments and locals of every thread.
+pub fn print_vars(number: usize, stack: &str, threads: &[Thread]) -> String {
+    let mut output = format!(
+        "[{number}] Arguments and locals of {} thread(s):\n",
+        threads.len()
+    );
+    for thread in threads {
+        output.push_str(&format!("Thread {}:\n", thread.tid));
+        for frame in stack.lines() {
+            output.push_str(frame);
+            output.push('\n');
+
+            let level = patterns::frame_function()
+                .captures(frame)
+                .and_then(|m| m.name("level").unwrap().as_str().parse::<usize>().ok());
+            let Some(vars) = level.and_then(|level| thread.vars.get(&level)) else {
+                continue;
+            };
+            for (name, value) in vars.args.iter() {
+                output.push_str(&format!("        (arg) {name} = {value}\n"));
+            }
+            for (name, value) in vars.locals.iter() {
+                output.push_str(&format!("        {name} = {value}\n"));
+            }
+        }
+    }
+
+    output
+}
+
+/// run_gdb_locals  -  show unique stacks of a process, then arguments and locals of chosen ones.
+// Stacks are chosen with `--locals N`, or interactively until the prompt is cancelled.
+pub async fn run_gdb_locals(cli: &Cli) {
+    let Some(pid) = cli.pids.as_ref().and_then(|pids| pids.first()) else {
+        eprintln!("Needs pid to capture locals.");
+        exit(2);
+    };
+    if cli.pids.as_ref().is_some_and(|pids| pids.len() > 1) {
+        eprintln!("Locals are captured for the first process only: {pid}");
+    }
+
+    let args: Vec<String> = ["--batch", "-p", pid, "-ex", "thread apply all bt full"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let samples = match take_samples("gdb", &args, Some(pid), None, 1).await {
+        Ok(samples) => samples,
+        Err(err) => {
+            eprintln!("Process {pid} returns error: {err}");
+            exit(2);
+        }
+    };
+
+    let filter = StackFilter::from_cli(cli);
+    let mut groups = StackGroups::new(filter.clone());
+    for sample in samples.iter() {
+        match sample.threads_with(InputFormat::Gdb, true) {
+            Ok(threads) => {
+                for mut thread in threads {
+                    prepare_thread(&mut thread, &filter, cli.raw_mode);
+                    groups.add(thread);
+                }
+            }
+            Err(err) => {
+                eprintln!("Process {pid} returns error: {err}");
+                exit(2);
+            }
+        }
+    }
+
+    let mut groups = groups.into_thread_groups();
+    sort_groups(&mut groups);
+    let labels: Vec<(String, Vec<String>)> = groups
+        .iter()
+        .map(|(stack, threads)| {
+            let tids = threads.iter().map(|t| t.tid.clone()).collect();
+            (stack.clone(), tids)
+        })
+        .collect();
+    println!("{}", print_stacks(&labels, true, &Notes::new()));
+
+    let show = |number: usize| match groups.get(number.wrapping_sub(1)) {
+        Some((stack, threads)) => println!("\n{}", print_vars(number, stack, threads)),
+        None => eprintln!("No stack numbered {number}."),
+    };
+    match cli.locals {
+        Some(number) if number > 0 => show(number),
+        _ => loop {
+            let choices: Vec<String> = labels
+                .iter()
+                .enumerate()
+                .map(|(i, (stack, tids))| {
+                    let top = stack.lines().next().unwrap_or_default();
+                    format!("[{}] {} thread(s): {}", i + 1, tids.len(), top)
+                })
+                .collect();
+            match Select::new("Show arguments and locals of stack: ", choices).raw_prompt() {
+                Ok(choice) => show(choice.index + 1),
+                Err(_) => break,
+            }
+        },
+    }
+    exit(0);
+}
+
+#[test]
+fn test_frame_arguments() {
+    assert_eq!(
+        frame_arguments(
+            "#6  0x000055723be891af in main (argc=1, argv=0x7ffec118b6f8) at test.c:19"
+        ),
+        vec![
+            ("argc".to_owned(), "1".to_owned()),
+            ("argv".to_owned(), "0x7ffec118b6f8".to_owned())
+        ]
+    );
+    assert_eq!(
+        frame_arguments(
+            r#"#2  0x0000563 in log (fmt=0x4005f4 "a, b (%d)", v={x = 1, y = 2}) at log.c:5"#
+        ),
+        vec![
+            ("fmt".to_owned(), r#"0x4005f4 "a, b (%d)""#.to_owned()),
+            ("v".to_owned(), "{x = 1, y = 2}".to_owned())
+        ]
+    );
+    assert_eq!(
+        frame_arguments("#4  Foo::bar (this=0x0) at foo.cc:10"),
+        vec![("this".to_owned(), "0x0".to_owned())]
+    );
+    assert!(frame_arguments("#3  0x000055723be89162 in func2 () at test.c:5").is_empty());
+    assert!(frame_arguments("#0  0x00007f83ddc5363f __poll").is_empty());
+}
+
+#[test]
+fn test_locals() {
+    let input = r##"
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e7 in __lll_lock_wait () from /usr/lib64/libc.so.6
+No symbol table info available.
+#1  0x000055723be89162 in flush (store=0x55d0c0, force=true) at store.c:42
+        lock = 0x55d0e8
+        buf = "#1 not a frame"
+        st = {a = 1,
+          b = 2}
+#2  0x000055723be8917d in main () at test.c:10
+No locals.
+"##;
+    // locals are looked for only when asked
+    assert!(InputFormat::Gdb.parse(input, None).unwrap()[0]
+        .vars
+        .is_empty());
+    let mut threads = InputFormat::Gdb.parse_with(input, None, true).unwrap();
+    assert_eq!(threads.len(), 1);
+    let thread = &mut threads[0];
+    assert_eq!(thread.frames.len(), 3);
+    assert_eq!(
+        thread.vars[&1].locals,
+        vec![
+            ("lock".to_owned(), "0x55d0e8".to_owned()),
+            ("buf".to_owned(), r##""#1 not a frame""##.to_owned()),
+            ("st".to_owned(), "{a = 1, b = 2}".to_owned())
+        ]
+    );
+
+    prepare_thread(thread, &StackFilter::default(), false);
+    assert_eq!(thread.frames[1], "#1  0x000055723be89162 flush");
+    assert_eq!(thread.vars[&1].args.len(), 2);
+    assert!(!thread.vars.contains_key(&2));
+
+    let output = print_vars(1, &thread.stack(), &threads);
+    assert!(output.starts_with("[1] Arguments and locals of 1 thread(s):\nThread 37748:\n"));
+    assert!(output.contains("flush\n        (arg) store = 0x55d0c0\n        (arg) force = true\n"));
+    assert!(output.contains("        lock = 0x55d0e8\n"));
+}
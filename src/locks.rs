@@ -0,0 +1,206 @@
+// Lock contention and deadlock detection.
+// Threads blocked on a lock are recognized by the frames at the top of their stacks. With the gdb
+// backend, owners of mutexes are read too, giving a wait-for graph whose cycles are deadlocks.
+
+use colored::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    patterns,
+    stack::{function_name, label_flags, Thread},
+};
+
+/// Number of frames, from the top of each stack, searched for lock functions.
+// Lock functions are called by wrappers (`std::mutex::lock`, `Mutex<T>::lock`...), but a lock
+// function deep in the stack is a lock being held rather than waited for.
+const LOCK_FRAMES: usize = 8;
+
+/// lock_wait  -  lock function `stack` is blocked in, None if it is not waiting for a lock.
+// Condition variables and semaphores wait on futexes too (`futex_wait` above `pthread_cond_wait`),
+// they are idle threads, not contention.
+pub fn lock_wait(stack: &str) -> Option<&str> {
+    let funcs: Vec<&str> = stack
+        .lines()
+        .filter(|s| patterns::frame_function().is_match(s))
+        .take(LOCK_FRAMES)
+        .map(function_name)
+        .collect();
+    if funcs
+        .iter()
+        .any(|func| patterns::lock_idle().is_match(func))
+    {
+        return None;
+    }
+    funcs
+        .into_iter()
+        .find(|func| patterns::lock_wait().is_match(func))
+}
+
+/// mutex_frame  -  level of topmost pthread mutex locking frame of `frames`.
+pub fn mutex_frame(frames: &[String]) -> Option<usize> {
+    lock_wait(&frames.join("\n"))?;
+
+    frames.iter().take(LOCK_FRAMES).find_map(|frame| {
+        let m = patterns::frame_function().captures(frame)?;
+        patterns::pthread_mutex_lock()
+            .is_match(function_name(frame))
+            .then(|| m.name("level").unwrap().as_str().parse().ok())
+            .flatten()
+    })
+}
+
+/// deadlocks  -  cycles of wait-for graph of `threads`, as labels of threads involved.
+// Every blocked thread waits for one owner at most, thus cycles are found by following owners
+// from each thread. Cycles are rotated to start from their lowest tid so each is reported once.
+// Threads are labelled as in unique stacks, with source and pid when threads come from several.
+pub fn deadlocks(threads: &[Thread]) -> Vec<Vec<String>> {
+    let (with_source, with_pid) = label_flags(threads);
+
+    type Process<'a> = (&'a Option<String>, &'a Option<String>);
+    let owners: HashMap<(Process, &str), (&str, &Thread)> = threads
+        .iter()
+        .filter_map(|t| {
+            t.lock_owner
+                .as_deref()
+                .map(|owner| (((&t.source, &t.pid), t.tid.as_str()), (owner, t)))
+        })
+        .collect();
+
+    let mut found = HashSet::new();
+    let mut cycles = vec![];
+    let mut starts: Vec<_> = owners.keys().collect();
+    starts.sort();
+    for (process, tid) in starts {
+        let mut path: Vec<&str> = vec![tid];
+        while let Some((owner, _)) = owners.get(&(*process, path[path.len() - 1])) {
+            if let Some(i) = path.iter().position(|t| t == owner) {
+                let mut cycle: Vec<&str> = path[i..].to_vec();
+                let min = (0..cycle.len())
+                    .min_by_key(|i| (cycle[*i].len(), cycle[*i]))
+                    .unwrap();
+                cycle.rotate_left(min);
+                if found.insert((process, cycle.clone())) {
+                    // every thread of a cycle waits for a lock, thus is an owner key
+                    cycles.push(
+                        cycle
+                            .iter()
+                            .map(|tid| owners[&(*process, *tid)].1.label(with_source, with_pid))
+                            .collect(),
+                    );
+                }
+                break;
+            }
+            path.push(owner);
+        }
+    }
+    cycles
+}
+
+/// print_deadlocks  -  summary of deadlocks found in `threads`, empty if none.
+pub fn print_deadlocks(threads: &[Thread]) -> String {
+    deadlocks(threads)
+        .into_iter()
+        .map(|cycle| {
+            let first = cycle[0].clone();
+            let chain = cycle.into_iter().chain([first]).collect::<Vec<_>>();
+            format!("\nDeadlock: {}", chain.join(" -> ").red().bold())
+        })
+        .collect()
+}
+
+#[test]
+fn test_lock_wait() {
+    for (stack, func) in [
+        (
+            "#0  0x00007f29ce8db9e7 __lll_lock_wait\n\
+             #1  0x00007f29ce8e2a55 pthread_mutex_lock\n\
+             #2  0x000055723be89162 flush\n",
+            Some("__lll_lock_wait"),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 futex_wait\n\
+             #1  0x00007f29ce8db9e7 __GI___pthread_mutex_lock\n",
+            Some("futex_wait"),
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 in ___pthread_rwlock_wrlock () from /usr/lib64/libc.so.6\n",
+            Some("___pthread_rwlock_wrlock"),
+        ),
+        (
+            "#0  0x0000563 syscall\n\
+             #1  0x0000563 std::sys::sync::mutex::futex::Mutex::lock_contended\n\
+             #2  0x0000563 std::sync::mutex::Mutex<T>::lock\n",
+            Some("std::sys::sync::mutex::futex::Mutex::lock_contended"),
+        ),
+        // condition variable waits on futex too
+        (
+            "#0  0x00007f29ce8db9e7 futex_wait\n\
+             #1  0x00007f29ce8db9e7 __futex_abstimed_wait_common\n\
+             #2  0x00007f29ce8e2a55 pthread_cond_wait\n",
+            None,
+        ),
+        ("#0  0x00007f83ddc5363f __poll\n", None),
+    ] {
+        assert_eq!(lock_wait(stack), func, "{stack}");
+    }
+
+    // listed with suspicious threads
+    let groups = vec![
+        (
+            "#0  0x1 __lll_lock_wait\n#1  0x2 pthread_mutex_lock\n".to_owned(),
+            vec!["101".to_owned(), "102".to_owned()],
+        ),
+        ("#0  0x1 __poll\n".to_owned(), vec!["103".to_owned()]),
+    ];
+    let output = crate::uniquify::print_stacks(&groups, false, &Default::default());
+    assert!(output.ends_with("Suspicious threads: 101, 102 (__lll_lock_wait)"));
+
+    let frames: Vec<String> = [
+        "#0  0x00007f29ce8db9e7 in __lll_lock_wait () from /usr/lib64/libc.so.6",
+        "#1  0x00007f29ce8e2a55 in ___pthread_mutex_lock () from /usr/lib64/libc.so.6",
+        "#2  0x000055723be89162 in flush () at store.c:42",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    assert_eq!(mutex_frame(&frames), Some(1));
+    assert_eq!(mutex_frame(&frames[2..]), None);
+}
+
+#[test]
+fn test_deadlocks() {
+    let thread = |tid: &str, owner: Option<&str>| Thread {
+        lock_owner: owner.map(|s| s.to_owned()),
+        ..Thread::new(None, Some("100"), tid)
+    };
+    let threads = vec![
+        thread("100", None),
+        thread("101", Some("102")),
+        thread("102", Some("103")),
+        thread("103", Some("101")),
+        // waits for a thread of the cycle, not part of it
+        thread("104", Some("101")),
+        thread("105", Some("100")),
+    ];
+    assert_eq!(deadlocks(&threads), vec![vec!["101", "102", "103"]]);
+
+    let output = print_deadlocks(&threads);
+    assert!(output.starts_with("\nDeadlock: "));
+    assert!(output.contains("101 -> 102 -> 103 -> 101"));
+    assert_eq!(print_deadlocks(&threads[..3]), "");
+
+    // same tids in other process are other threads
+    let mut other = threads.clone();
+    for t in other.iter_mut() {
+        t.pid = Some("200".to_owned());
+    }
+    other.extend(threads);
+    assert_eq!(
+        deadlocks(&other),
+        vec![
+            vec!["100:101", "100:102", "100:103"],
+            vec!["200:101", "200:102", "200:103"]
+        ]
+    );
+    assert!(print_deadlocks(&other).contains("200:101 -> 200:102 -> 200:103 -> 200:101"));
+}
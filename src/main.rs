@@ -1,23 +1,24 @@
-mod utils;
-
-mod args;
-mod eu_stack;
-mod gdb;
-mod uniquify;
-
 use std::process::exit;
 
-use crate::args::parse_args;
-use crate::eu_stack::run_eustack;
-use gdb::run_gdb;
-use uniquify::uniquify_stack_files;
-use utils::{choose_process, execute_command, list_process};
+use cs::args::{parse_args, Command};
+use cs::crash::run_on_crash;
+use cs::eu_stack::run_eustack;
+use cs::gdb::run_gdb;
+use cs::history::run_history;
+use cs::trigger::wait_trigger;
+use cs::uniquify::uniquify_stack_files;
+use cs::utils::{self, choose_process, execute_command, list_process};
 
 #[tokio::main]
 async fn main() {
     let _ = utils::get_terminal_size(); // must be done before setup pager
     let mut cli = parse_args(std::env::args());
 
+    if let Some(Command::History { id }) = cli.command.clone() {
+        run_history(cli, id).await;
+        return;
+    }
+
     if !cli.gdb_mode {
         if let Ok((code, _out, _err)) = execute_command("which", ["eu-stack"]).await {
             if code != 0 {
@@ -43,6 +44,15 @@ async fn main() {
         }
     }
 
+    if cli.on_crash {
+        run_on_crash(&cli).await;
+    }
+
+    if !cli.triggers.is_empty() {
+        let pids = wait_trigger(&cli).await;
+        cli.pids.replace(pids);
+    }
+
     if cli.list {
         list_process(cli).await;
     } else if !cli.files.is_empty() {
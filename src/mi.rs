@@ -0,0 +1,590 @@
+// GDB/MI (machine interface) session, used to capture stacks of running processes.
+// Threads and frames arrive as structured records, instead of text of `thread apply all backtrace`
+// scraped with regexes. Threads are built from them, with frames rendered as gdb prints them, so
+// that frames are handled like those of dumps.
+
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    time::{timeout_at, Instant},
+};
+
+use crate::{locks::mutex_frame, sample::Sample, stack::Thread};
+
+/// Time gdb is given to reply to a command, attaching and reading symbols included.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Value of a GDB/MI result.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    // c-string, unescaped
+    Const(String),
+    // `{name=value,...}`
+    Tuple(Vec<(String, Value)>),
+    // `[value,...]` or `[name=value,...]`, names of results in lists are dropped
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// get  -  value of result `name` of a tuple.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Tuple(results) => results.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// str  -  string of result `name` of a tuple.
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(Value::Const(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn items(&self) -> &[Value] {
+        match self {
+            Value::List(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Output record of GDB/MI, one per line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    // `token^class,results`, reply to a command
+    Result {
+        token: Option<u64>,
+        class: String,
+        results: Value,
+    },
+    // `*stopped,...`, `=thread-created,...`...
+    Async(String, Value),
+    // `~"text"` (console), `@"text"` (target) or `&"text"` (log)
+    Stream(char, String),
+    // `(gdb)`
+    Prompt,
+}
+
+/// Cursor over a line of GDB/MI output.
+struct Scanner<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expects '{c}' at {}: {}", self.pos, self.s))
+        }
+    }
+
+    fn name(&mut self) -> &str {
+        let start = self.pos;
+        let len = self.s[start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.s.len() - start);
+        self.pos += len;
+        &self.s[start..self.pos]
+    }
+
+    /// c_string  -  quoted string, with C escapes unescaped.
+    // Non-ASCII bytes are escaped one by one in octal, thus bytes are collected then decoded.
+    fn c_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = vec![];
+        let push = |result: &mut Vec<u8>, c: char| {
+            result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        };
+        let mut chars = self.s[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(String::from_utf8_lossy(&result).into_owned());
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => result.push(b'\n'),
+                    Some('t') => result.push(b'\t'),
+                    Some('r') => result.push(b'\r'),
+                    Some(c) if c.is_digit(8) => {
+                        // octal escape of non-printable bytes, `\033` or `\303\251`
+                        let mut code = c.to_digit(8).unwrap();
+                        for _ in 0..2 {
+                            match chars.clone().next() {
+                                Some((_, d)) if d.is_digit(8) => {
+                                    code = code * 8 + d.to_digit(8).unwrap();
+                                    chars.next();
+                                }
+                                _ => break,
+                            }
+                        }
+                        result.push(code as u8);
+                    }
+                    Some(c) => push(&mut result, c),
+                    None => break,
+                },
+                c => push(&mut result, c),
+            }
+        }
+        Err(format!("unterminated string: {}", self.s))
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => Ok(Value::Const(self.c_string()?)),
+            Some('{') => {
+                self.pos += 1;
+                let mut results = vec![];
+                if !self.eat('}') {
+                    loop {
+                        results.push(self.result()?);
+                        if self.eat('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Tuple(results))
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                if !self.eat(']') {
+                    loop {
+                        if matches!(self.peek(), Some('"' | '{' | '[')) {
+                            items.push(self.value()?);
+                        } else {
+                            items.push(self.result()?.1);
+                        }
+                        if self.eat(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::List(items))
+            }
+            _ => Err(format!("expects value at {}: {}", self.pos, self.s)),
+        }
+    }
+
+    fn result(&mut self) -> Result<(String, Value), String> {
+        let name = self.name().to_owned();
+        if name.is_empty() {
+            return Err(format!("expects name at {}: {}", self.pos, self.s));
+        }
+        self.expect('=')?;
+        Ok((name, self.value()?))
+    }
+
+    /// results  -  `,name=value` pairs until the end of line, as a tuple.
+    fn results(&mut self) -> Result<Value, String> {
+        let mut results = vec![];
+        while self.eat(',') {
+            results.push(self.result()?);
+        }
+        if self.pos < self.s.len() {
+            return Err(format!("unexpected text at {}: {}", self.pos, self.s));
+        }
+        Ok(Value::Tuple(results))
+    }
+}
+
+/// parse_record  -  parse line `s` of GDB/MI output.
+pub fn parse_record(s: &str) -> Result<Record, String> {
+    let s = s.trim_end();
+    if s == "(gdb)" {
+        return Ok(Record::Prompt);
+    }
+
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let token = s[..digits].parse().ok();
+    let mut scanner = Scanner { s, pos: digits };
+    match scanner.peek() {
+        Some(kind @ ('~' | '@' | '&')) => {
+            scanner.pos += 1;
+            Ok(Record::Stream(kind, scanner.c_string()?))
+        }
+        Some('^') => {
+            scanner.pos += 1;
+            let class = scanner.name().to_owned();
+            Ok(Record::Result {
+                token,
+                class,
+                results: scanner.results()?,
+            })
+        }
+        Some('*' | '+' | '=') => {
+            scanner.pos += 1;
+            let class = scanner.name().to_owned();
+            Ok(Record::Async(class, scanner.results()?))
+        }
+        _ => Err(format!("unknown record: {s}")),
+    }
+}
+
+/// lwp  -  kernel thread id in `target-id` of thread, `Thread 0x7f (LWP 37748)` or `process 37746`.
+fn lwp(target_id: &str) -> Option<&str> {
+    ["LWP ", "process "].iter().find_map(|prefix| {
+        let rest = &target_id[target_id.find(prefix)? + prefix.len()..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        Some(&rest[..end]).filter(|s| !s.is_empty())
+    })
+}
+
+/// render_args  -  arguments of a frame of `-stack-list-arguments --simple-values`, as gdb
+/// prints them in backtraces.
+// Values of aggregates are not listed with `--simple-values`, gdb prints them as `...` too.
+fn render_args(args: &Value) -> String {
+    args.get("args")
+        .map_or(&[][..], Value::items)
+        .iter()
+        .filter_map(|arg| {
+            let name = arg.str("name")?;
+            Some(format!("{name}={}", arg.str("value").unwrap_or("...")))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// render_frame  -  frame of `-stack-list-frames` as gdb prints it in backtraces, with `args` of
+/// the frame if listed.
+// Absolute `fullname` is preferred to `file`, often relative to the build directory, so that
+// source of the frame can be read.
+pub fn render_frame(frame: &Value, args: Option<&Value>) -> String {
+    let level = frame.str("level").unwrap_or("?");
+    let mut line = format!("#{level:<3}");
+    if let Some(addr) = frame.str("addr") {
+        line.push_str(addr);
+        line.push_str(" in ");
+    }
+    line.push_str(frame.str("func").unwrap_or("??"));
+    line.push_str(&format!(" ({})", args.map(render_args).unwrap_or_default()));
+    let file = frame.str("fullname").or_else(|| frame.str("file"));
+    match (file, frame.str("line"), frame.str("from")) {
+        (Some(file), Some(n), _) => line.push_str(&format!(" at {file}:{n}")),
+        (_, _, Some(from)) => line.push_str(&format!(" from {from}")),
+        _ => {}
+    }
+    line
+}
+
+/// mi_thread  -  thread of `-thread-info`, with frames of its `stack` of `-stack-list-frames`
+/// and `args` of `-stack-list-arguments` if listed.
+// Thread is built from values, names are taken as is whatever they hold. Process and state of the
+// thread are filled along with the sample.
+pub fn mi_thread(thread: &Value, stack: &Value, args: Option<&Value>) -> Option<Thread> {
+    let tid = thread.str("target-id").and_then(lwp)?;
+    let args = args.map_or(&[][..], Value::items);
+    let mut result = Thread::new(None, None, tid);
+    result.name = thread.str("name").map(|s| s.to_owned());
+    result.frames = stack
+        .items()
+        .iter()
+        .map(|frame| {
+            let args = args.iter().find(|a| a.str("level") == frame.str("level"));
+            render_frame(frame, args)
+        })
+        .collect();
+    Some(result)
+}
+
+/// render_thread  -  `thread` of gdb thread `id` as gdb `thread apply all backtrace` prints it.
+pub fn render_thread(id: &str, thread: &Thread) -> String {
+    let mut output = format!("\nThread {id} (LWP {}", thread.tid);
+    if let Some(name) = &thread.name {
+        output.push_str(&format!(" \"{name}\""));
+    }
+    output.push_str("):\n");
+    for frame in thread.frames.iter() {
+        output.push_str(frame);
+        output.push('\n');
+    }
+    output
+}
+
+/// A gdb process driven through GDB/MI on its stdin and stdout.
+pub struct MiSession {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+    token: u64,
+    /// Log stream of gdb, reported when commands fail.
+    log: Vec<String>,
+}
+
+impl MiSession {
+    /// attach  -  start gdb attached to process `pid`.
+    pub fn attach(pid: &str) -> Result<MiSession, String> {
+        let mut child = Command::new("gdb")
+            .args(["--interpreter=mi", "--quiet", "-p", pid])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("failed to run gdb: {err}"))?;
+        let stdin = child.stdin.take().unwrap();
+        let lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        Ok(MiSession {
+            child,
+            stdin,
+            lines,
+            token: 0,
+            log: vec![],
+        })
+    }
+
+    /// command  -  run MI command `cmd`, returns results of its `^done`.
+    // A process gdb cannot stop, such as one in uninterruptible sleep, leaves gdb waiting for
+    // ever, thus the reply is waited for `COMMAND_TIMEOUT` at most.
+    pub async fn command(&mut self, cmd: &str) -> Result<Value, String> {
+        self.token += 1;
+        self.stdin
+            .write_all(format!("{}{cmd}\n", self.token).as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let deadline = Instant::now() + COMMAND_TIMEOUT;
+        while let Some(line) = timeout_at(deadline, self.lines.next_line())
+            .await
+            .map_err(|_| format!("{cmd}: no reply of gdb in {COMMAND_TIMEOUT:?}"))?
+            .map_err(|err| err.to_string())?
+        {
+            match parse_record(&line) {
+                Ok(Record::Result {
+                    token: Some(token),
+                    class,
+                    results,
+                }) if token == self.token => {
+                    return match class.as_str() {
+                        "done" => Ok(results),
+                        _ => Err(results
+                            .str("msg")
+                            .map_or_else(|| format!("{cmd}: {class}"), |s| s.to_owned())),
+                    };
+                }
+                Ok(Record::Stream('&', text)) => self.log.push(text.trim_end().to_owned()),
+                _ => {}
+            }
+        }
+
+        Err(format!("gdb exits: {}", self.log.join(" ")))
+    }
+
+    /// list  -  list `name` of results of MI command `cmd` for thread `id`, empty if none.
+    async fn list(&mut self, id: &str, cmd: &str, name: &str) -> Result<Value, String> {
+        let results = self.command(&format!("{cmd} --thread {id}")).await?;
+        Ok(results.get(name).cloned().unwrap_or(Value::List(vec![])))
+    }
+
+    /// lock_owner  -  tid of owner of the mutex locked in frame `level` of thread `id`.
+    // `mutex` is known with debug info of libc only. Otherwise the futex waited for is the first
+    // argument of the syscall, in `rdi` on x86-64, and the owner follows the lock and count fields.
+    async fn lock_owner(&mut self, id: &str, level: usize) -> Option<String> {
+        for (frame, expr) in [(level, "mutex->__data.__owner"), (0, "*((int *) $rdi + 2)")] {
+            let cmd = format!("-data-evaluate-expression --thread {id} --frame {frame} \"{expr}\"");
+            // `<optimized out>` or 0 are no better than an error, try the next expression
+            let owner = self.command(&cmd).await.ok().and_then(|value| {
+                value
+                    .str("value")
+                    .and_then(|s| s.parse::<i64>().ok())
+                    .filter(|owner| *owner > 0)
+            });
+            if let Some(owner) = owner {
+                return Some(owner.to_string());
+            }
+        }
+        None
+    }
+
+    /// capture  -  stacks of all threads, with owners of mutexes threads are blocked on, and
+    /// arguments of frames if `args`.
+    pub async fn capture(&mut self, args: bool) -> Result<Sample, String> {
+        let threads = self.command("-thread-info").await?;
+        let mut output = String::new();
+        let mut records = vec![];
+        for thread in threads.get("threads").map_or(&[][..], Value::items) {
+            let Some(id) = thread.str("id") else {
+                continue;
+            };
+            let stack = self.list(id, "-stack-list-frames", "stack").await?;
+            let args = match args {
+                true => Some(
+                    self.list(id, "-stack-list-arguments --simple-values", "stack-args")
+                        .await?,
+                ),
+                false => None,
+            };
+            let Some(mut record) = mi_thread(thread, &stack, args.as_ref()) else {
+                continue;
+            };
+
+            if let Some(level) = mutex_frame(&record.frames) {
+                record.lock_owner = self.lock_owner(id, level).await;
+            }
+            output.push_str(&render_thread(id, &record));
+            records.push(record);
+        }
+        if records.is_empty() {
+            return Err(format!("no thread found: {}", self.log.join(" ")));
+        }
+
+        Ok(Sample {
+            output,
+            records,
+            ..Default::default()
+        })
+    }
+
+    /// exit  -  detach from process and wait for gdb to exit.
+    pub async fn exit(mut self) {
+        let _ = self.stdin.write_all(b"-gdb-exit\n").await;
+        let _ = self.child.wait().await;
+    }
+}
+
+/// capture  -  stacks of all threads of process `pid`, captured through GDB/MI.
+pub async fn capture(pid: &str, args: bool) -> Result<Sample, String> {
+    let mut session = MiSession::attach(pid)?;
+    let result = session.capture(args).await;
+    session.exit().await;
+    result
+}
+
+#[test]
+fn test_parse_record() {
+    assert_eq!(parse_record("(gdb) "), Ok(Record::Prompt));
+    assert_eq!(
+        parse_record(r#"~"Reading symbols from \"/usr/bin/test\"...\n""#),
+        Ok(Record::Stream(
+            '~',
+            "Reading symbols from \"/usr/bin/test\"...\n".to_owned()
+        ))
+    );
+    assert_eq!(
+        parse_record(r#"=thread-group-added,id="i1""#),
+        Ok(Record::Async(
+            "thread-group-added".to_owned(),
+            Value::Tuple(vec![("id".to_owned(), Value::Const("i1".to_owned()))])
+        ))
+    );
+    assert_eq!(
+        parse_record(r#"3^error,msg="Invalid thread id: 9""#),
+        Ok(Record::Result {
+            token: Some(3),
+            class: "error".to_owned(),
+            results: Value::Tuple(vec![(
+                "msg".to_owned(),
+                Value::Const("Invalid thread id: 9".to_owned())
+            )])
+        })
+    );
+    assert_eq!(
+        parse_record("^done"),
+        Ok(Record::Result {
+            token: None,
+            class: "done".to_owned(),
+            results: Value::Tuple(vec![])
+        })
+    );
+
+    let Ok(Record::Result { results, .. }) = parse_record(
+        r#"2^done,stack=[frame={level="0",addr="0x1",func="a"},frame={level="1",addr="0x2"}],x=["1","2"],e={},l=[]"#,
+    ) else {
+        panic!();
+    };
+    let frames = results.get("stack").unwrap().items();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].str("func"), Some("a"));
+    assert_eq!(frames[1].str("func"), None);
+    assert_eq!(results.get("x").unwrap().items().len(), 2);
+    assert_eq!(results.get("e"), Some(&Value::Tuple(vec![])));
+    assert_eq!(results.get("l"), Some(&Value::List(vec![])));
+
+    assert_eq!(
+        parse_record(r#"&"caf\303\251 \033[0m\n""#),
+        Ok(Record::Stream('&', "caf\u{e9} \u{1b}[0m\n".to_owned()))
+    );
+    assert!(parse_record(r#"1^done,msg="unterminated"#).is_err());
+    assert!(parse_record("Reading symbols").is_err());
+}
+
+#[test]
+fn test_mi_thread() {
+    let results = |s: &str| match parse_record(s) {
+        Ok(Record::Result { results, .. }) => results,
+        _ => panic!(),
+    };
+
+    // replies of gdb 14 attached to a process with 2 threads
+    let threads = results(
+        r#"1^done,threads=[{id="2",target-id="Thread 0x7f29ce0006c0 (LWP 37748)",name="worker \"io\" (2)",frame={level="0",addr="0x00007f29ce8db9e7",func="__lll_lock_wait",args=[],from="/usr/lib64/libc.so.6",arch="i386:x86-64"},state="stopped",core="3"},{id="1",target-id="Thread 0x7f29ce816740 (LWP 37746)",name="test",frame={level="0",addr="0x00007f29ce8db9e7",func="clock_nanosleep",args=[],from="/usr/lib64/libc.so.6",arch="i386:x86-64"},state="stopped",core="1"}],current-thread-id="1""#,
+    );
+    let threads = threads.get("threads").unwrap().items();
+    let stacks: Vec<Value> = [
+        r#"2^done,stack=[frame={level="0",addr="0x00007f29ce8db9e7",func="__lll_lock_wait",from="/usr/lib64/libc.so.6",arch="i386:x86-64"},frame={level="1",addr="0x000055723be89162",func="flush",file="store.c",fullname="/home/me/store.c",line="42",arch="i386:x86-64"}]"#,
+        r#"3^done,stack=[frame={level="0",addr="0x00007f29ce8db9e7",func="clock_nanosleep",from="/usr/lib64/libc.so.6",arch="i386:x86-64"},frame={level="1",addr="0x00000000004011e0",arch="i386:x86-64"},frame={level="10",addr="0x000055723be891af",func="main",file="test.c",line="19",arch="i386:x86-64"}]"#,
+    ]
+    .iter()
+    .map(|s| results(s).get("stack").unwrap().clone())
+    .collect();
+
+    let worker = mi_thread(&threads[0], &stacks[0], None).unwrap();
+    assert_eq!(worker.tid, "37748");
+    // taken as is, even with quotes and parentheses
+    assert_eq!(worker.name.as_deref(), Some("worker \"io\" (2)"));
+    assert_eq!(
+        worker.frames,
+        vec![
+            "#0  0x00007f29ce8db9e7 in __lll_lock_wait () from /usr/lib64/libc.so.6",
+            "#1  0x000055723be89162 in flush () at /home/me/store.c:42",
+        ]
+    );
+    let main = mi_thread(&threads[1], &stacks[1], None).unwrap();
+    assert_eq!(
+        render_thread("1", &main),
+        r#"
+Thread 1 (LWP 37746 "test"):
+#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6
+#1  0x00000000004011e0 in ?? ()
+#10 0x000055723be891af in main () at test.c:19
+"#
+    );
+
+    // arguments listed in raw mode, values of aggregates are not
+    let args = results(
+        r#"4^done,stack-args=[frame={level="0",args=[]},frame={level="1",args=[{name="fd",type="int",value="3"},{name="log",type="struct log"}]}]"#,
+    );
+    let worker = mi_thread(&threads[0], &stacks[0], args.get("stack-args")).unwrap();
+    assert_eq!(
+        worker.frames[1],
+        "#1  0x000055723be89162 in flush (fd=3, log=...) at /home/me/store.c:42"
+    );
+
+    assert_eq!(lwp("process 37746"), Some("37746"));
+    assert_eq!(lwp("Thread 0x7f29ce816740 (LWP 37748)"), Some("37748"));
+    assert_eq!(lwp("Remote target"), None);
+    let remote = results(r#"^done,id="1",target-id="Remote target""#);
+    assert!(mi_thread(&remote, &stacks[0], None).is_none());
+}
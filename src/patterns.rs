@@ -0,0 +1,158 @@
+// Regular expressions shared by parsers.
+// Every regex is compiled once, the first time it is used, as parsers run for every line of every
+// sample of every process.
+
+use regex::Regex;
+use std::sync::OnceLock;
+
+macro_rules! regex {
+    ($(#[$meta:meta])* $name:ident, $pattern:expr) => {
+        $(#[$meta])*
+        pub fn $name() -> &'static Regex {
+            static RE: OnceLock<Regex> = OnceLock::new();
+            RE.get_or_init(|| Regex::new($pattern).unwrap())
+        }
+    };
+}
+
+pub const RE_MATCH_GDB_TID: &str = r#"Thread\s+(?P<tid>\d+)\s+.*\(LWP\s+(?P<lwp>\d+).*\):"#;
+
+regex!(
+    /// `PID 14794 - process` of eu-stack
+    eustack_pid,
+    r#"PID\s+(?P<pid>\d+)\s+-\s+process"#
+);
+regex!(
+    /// `TID 14794:` of eu-stack
+    eustack_tid,
+    r#"TID\s+(?P<tid>\d+):"#
+);
+regex!(
+    /// frame of eu-stack
+    eustack_entry,
+    r#"^#\d+\s+0x.*?$"#
+);
+regex!(
+    /// `Thread 15 (Thread 0x7fa1aea006c0 (LWP 1175) "waybar"):` of gdb
+    gdb_tid,
+    RE_MATCH_GDB_TID
+);
+regex!(
+    /// quoted thread name at the end of gdb thread header
+    gdb_thread_name,
+    r#""(?P<name>.*)"\)?:\s*$"#
+);
+regex!(
+    /// frame of gdb
+    gdb_entry,
+    r#"\s*#\s*\d+\s+"#
+);
+regex!(
+    /// start of gdb frame, anchored
+    gdb_frame_start,
+    r#"^\s*#\s*\d+\s"#
+);
+regex!(
+    /// local variable of gdb `bt full`, `        lock = 0x7ffd1234`
+    gdb_local,
+    r#"^ {8}(?P<name>[^\s=][^=]*?) = (?P<value>.*)$"#
+);
+regex!(
+    /// arguments of gdb frame, `in main (argc=1, argv=0x7ffec118b6f8) at test.c:19`
+    gdb_frame_arguments,
+    r#"^\s*#\s*\d+\s+(0x[[:xdigit:]]+\s+in\s+)?\S.*?\s+\((?P<args>.*)\)(\s+(at|from)\s+\S+)?\s*$"#
+);
+regex!(
+    /// end of gdb output
+    gdb_detach,
+    r#"Inferior.*detached"#
+);
+
+regex!(
+    /// thread header of eu-stack, used for format detection
+    detect_eustack_header,
+    r#"^(PID\s+\d+\s+-\s+process|TID\s+\d+:)"#
+);
+regex!(
+    /// frame of eu-stack, used for format detection
+    detect_eustack_entry,
+    r#"^#\d+\s+0x[[:xdigit:]]+(\s+[^(\s]\S*.*)?$"#
+);
+regex!(
+    /// frame of gdb, used for format detection
+    detect_gdb_entry,
+    r#"^\s*#\d+\s+(0x[[:xdigit:]]+\s+in\s+)?\S+\s+\(.*\)"#
+);
+
+regex!(
+    /// frame level and function of a frame, in any format
+    frame_function,
+    r#"^\s*#\s*(?P<level>\d+)\s+(0x[[:xdigit:]]+\s*)?(in\s+)?(?P<func>.*?)\s*$"#
+);
+regex!(
+    /// source location at the end of a frame, `at test.c:5`
+    frame_location,
+    r#"\sat\s+(?P<file>\S+):(?P<line>\d+)\s*$"#
+);
+regex!(
+    /// parts of a frame kept when frames are shortened
+    short_frame,
+    r#"^(?P<level>\s*#\s*\d+)\s+(0x[[:xdigit:]]+\s*)?(in\s+)?(?P<func>.*?)(\s+\(.*\))?(\s+at\s+(?P<file>\S+?)(:(?P<line>\d+))?)?\s*$"#
+);
+regex!(
+    /// frame without symbol, of eu-stack (`#19 0x00007f83ddb902e0`) or gdb (`in ?? ()`)
+    unresolved_frame,
+    r#"^(?P<prefix>\s*#\s*(?P<level>\d+)\s+)(?P<addr>0x[[:xdigit:]]+)(?P<gdb>\s+in\s+\?\?\s+\(\)(?P<from>\s+from\s+.*)?)?\s*$"#
+);
+regex!(
+    /// suspicious frames, highlighted when printing stacks
+    suspicious,
+    &format!(
+        r#"(?i)(?P<sus>.*({}).*)"#,
+        [
+            "__assert_fail",
+            "fatal.*signals",
+            "raise",
+            "segfault",
+            "segment fault",
+            "segmentfault",
+            "signal handler called",
+        ]
+        .join("|")
+    )
+);
+regex!(
+    /// functions waiting for a lock, `pthread_mutex_lock`, `std::sync::Mutex<T>::lock`...
+    lock_wait,
+    r#"^(_*(GI_)*_*pthread_mutex_\w*lock\w*|_*(GI_)*_*pthread_rwlock_\w*lock|__lll_lock_wait\w*|futex_wait|.*::(Mutex|RwLock)(<.*>)?::(lock|read|write)\w*|.*RawMutex::lock_slow|.*RawRwLock::lock_\w+_slow)$"#
+);
+regex!(
+    /// functions waiting for a condition, their futex waits are not lock contention
+    lock_idle,
+    r#"(pthread_cond_\w*wait|Condvar(<.*>)?::wait|sem_\w*wait|pthread_barrier_wait|pthread_join)"#
+);
+regex!(
+    /// functions waiting for events or work
+    idle_wait,
+    r#"^_*(GI_)*_*(libc_)?(epoll_wait|epoll_pwait2?|poll|ppoll|select|pselect6?|sigwait|sigwaitinfo|sigtimedwait|sigsuspend|accept4?|io_getevents|io_uring_enter|pthread_join|futex_wait|futex_abstimed_wait\w*|wait4|waitpid|waitid)(_nocancel)?(@\S*)?$"#
+);
+regex!(
+    /// functions doing I/O
+    io_wait,
+    r#"^_*(GI_)*_*(libc_)?(read|write|readv|writev|pread|pwrite|pread64|pwrite64|preadv2?|pwritev2?|recv|recvfrom|recvmsg|recvmmsg|send|sendto|sendmsg|sendmmsg|fsync|fdatasync|sync_file_range|open|open64|openat|openat64|close|connect|sendfile|sendfile64|copy_file_range|splice|fallocate|fallocate64|ftruncate|ftruncate64)(_nocancel)?(@\S*)?$"#
+);
+regex!(
+    /// functions sleeping
+    sleep_wait,
+    r#"(^_*(GI_)*_*(clock_nanosleep|nanosleep|usleep|sleep|pause|thrd_sleep)(@\S*)?$|^std::thread::sleep)"#
+);
+regex!(
+    /// pthread mutex locking functions, owner of mutex is read in their frame
+    pthread_mutex_lock,
+    r#"^_*(GI_)*_*pthread_mutex_(timed|clock)?lock$"#
+);
+regex!(
+    /// pid column of `ps` output
+    ps_pid,
+    r#"\s*(?P<pid>\d+)\s+"#
+);
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+/// Status of a thread, read from `/proc/<pid>/task/<tid>/stat`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaskStat {
+    /// Thread name (`comm`)
+    pub comm: String,
+    /// Scheduler state, `R` for running, `S` for sleeping, `D` for disk sleep...
+    pub state: char,
+    /// Time spent in user mode, in clock ticks
+    pub utime: u64,
+    /// Time spent in kernel mode, in clock ticks
+    pub stime: u64,
+}
+
+/// parse_stat  -  parse content of `/proc/<pid>/task/<tid>/stat`.
+// comm is wrapped in parentheses and may contain spaces and parentheses itself, thus fields are
+// located from the last ')'.
+pub fn parse_stat(content: &str) -> Option<TaskStat> {
+    let start = content.find('(')?;
+    let end = content.rfind(')')?;
+    let comm = content.get(start + 1..end)?.to_owned();
+    let fields: Vec<&str> = content.get(end + 1..)?.split_whitespace().collect();
+
+    // fields after comm: state(3) ppid(4) ... utime(14) stime(15)
+    Some(TaskStat {
+        comm,
+        state: fields.first()?.chars().next()?,
+        utime: fields.get(11)?.parse().ok()?,
+        stime: fields.get(12)?.parse().ok()?,
+    })
+}
+
+/// read_task_stats  -  read status of all threads of process `pid`, keyed by thread id.
+// Returns an empty map if the process is gone or not readable.
+pub fn read_task_stats(pid: &str) -> HashMap<String, TaskStat> {
+    let mut stats = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(format!("/proc/{pid}/task")) else {
+        return stats;
+    };
+
+    for entry in entries.flatten() {
+        let tid = entry.file_name().to_string_lossy().to_string();
+        if let Some(stat) = std::fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|content| parse_stat(&content))
+        {
+            stats.insert(tid, stat);
+        }
+    }
+
+    stats
+}
+
+/// parse_rss  -  resident set size in bytes, from content of `/proc/<pid>/status`.
+pub fn parse_rss(content: &str) -> Option<u64> {
+    let line = content.lines().find(|s| s.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// read_rss  -  resident set size of process `pid` in bytes, None if it is gone or a kernel thread.
+pub fn read_rss(pid: &str) -> Option<u64> {
+    parse_rss(&std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?)
+}
+
+#[test]
+fn test_parse_stat() {
+    let stat = parse_stat(
+        "1175 (tokio-runtime-w) S 1 1175 1175 0 -1 4194560 2755 0 0 0 120 35 0 0 20 0 17 0 \
+         2131 1025544192 5632 18446744073709551615 1 1 0 0 0 0 0 4096 16387 0 0 0 17 3 0 0 0 0 0",
+    )
+    .unwrap();
+    assert_eq!(stat.comm, "tokio-runtime-w");
+    assert_eq!(stat.state, 'S');
+    assert_eq!(stat.utime, 120);
+    assert_eq!(stat.stime, 35);
+
+    let stat = parse_stat("42 (a (weird) name) D 1 42 42 0 -1 0 0 0 0 0 7 9 0 0").unwrap();
+    assert_eq!(stat.comm, "a (weird) name");
+    assert_eq!(stat.state, 'D');
+    assert_eq!(stat.utime, 7);
+    assert_eq!(stat.stime, 9);
+
+    assert!(parse_stat("42 (truncated").is_none());
+
+    let stats = read_task_stats(&std::process::id().to_string());
+    assert!(!stats.is_empty());
+    assert!(read_task_stats("not-a-pid").is_empty());
+}
+
+#[test]
+fn test_parse_rss() {
+    let status = "Name:\tcs\nVmPeak:\t   12000 kB\nVmRSS:\t    5632 kB\nThreads:\t17\n";
+    assert_eq!(parse_rss(status), Some(5632 * 1024));
+    assert_eq!(parse_rss("Name:\tkthreadd\nThreads:\t1\n"), None);
+    assert!(read_rss(&std::process::id().to_string()).is_some());
+}
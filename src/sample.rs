@@ -0,0 +1,298 @@
+use std::{collections::HashMap, future::Future, path::PathBuf, time::SystemTime};
+
+use crate::{
+    args::Cli,
+    filter::StackFilter,
+    format::InputFormat,
+    history::{archive_samples, history_dir},
+    hot::HotFrames,
+    locks::print_deadlocks,
+    procfs::{read_task_stats, TaskStat},
+    stack::Thread,
+    stuck::{print_stuck, stuck_threads},
+    symbolize::{read_maps, Mapping},
+    timeline::Timeline,
+    uniquify::{print_unique, StackGroups},
+    utils::{execute_command, format_time},
+};
+
+/// Output of one run of eu-stack or gdb.
+#[derive(Clone, Debug, Default)]
+pub struct Sample {
+    /// Process sampled, None for core files.
+    pub pid: Option<String>,
+    /// Output of the backend as shown, frames may be resolved or simplified.
+    pub output: String,
+    /// Output of the backend as is, archived.
+    pub raw: String,
+    /// Threads built from records of the backend, frames as shown. Output is parsed if empty.
+    pub records: Vec<Thread>,
+    /// Threads built from records of the backend as is, archived.
+    pub raw_records: Vec<Thread>,
+    /// Status of threads, read right before running the backend, as it stops all of them.
+    pub tasks: HashMap<String, TaskStat>,
+    /// Memory map of process, read along with status of threads.
+    pub maps: Vec<Mapping>,
+    /// Wall-clock time the sample is taken at.
+    pub time: Option<SystemTime>,
+}
+
+impl Sample {
+    /// threads  -  threads of this sample, with their name and state filled.
+    pub fn threads(&self, format: InputFormat) -> Result<Vec<Thread>, String> {
+        self.threads_with(format, false)
+    }
+
+    /// threads_with  -  threads of this sample, with locals of gdb `bt full` if `locals`.
+    pub fn threads_with(&self, format: InputFormat, locals: bool) -> Result<Vec<Thread>, String> {
+        let mut threads = match self.records.is_empty() {
+            true => format.parse_with(&self.output, None, locals)?,
+            false => self.records.clone(),
+        };
+        for thread in threads.iter_mut() {
+            if thread.pid.is_none() {
+                thread.pid.clone_from(&self.pid);
+            }
+            if let Some(stat) = self.tasks.get(&thread.tid) {
+                if thread.name.is_none() {
+                    thread.name = Some(stat.comm.clone());
+                }
+                thread.state = Some(stat.state);
+            }
+        }
+        Ok(threads)
+    }
+}
+
+/// take_samples  -  run `command` `count` times, with `interval` seconds in between.
+pub async fn take_samples(
+    command: &str,
+    args: &[String],
+    pid: Option<&str>,
+    interval: Option<f32>,
+    count: i32,
+) -> Result<Vec<Sample>, String> {
+    take_samples_with(pid, interval, count, || async {
+        match execute_command(command, args).await {
+            Ok((code, out, err)) => {
+                if code <= 1 {
+                    if !err.is_empty() {
+                        eprintln!("Warnings reported: {err}");
+                    }
+                    Ok(Sample {
+                        output: out,
+                        ..Default::default()
+                    })
+                } else {
+                    Err(err)
+                }
+            }
+            Err(err) => Err(err.to_string()),
+        }
+    })
+    .await
+}
+
+/// take_samples_with  -  call `capture` `count` times, with `interval` seconds in between.
+// `capture` fills what the backend reports, process and status of its threads are filled here.
+pub async fn take_samples_with<F, Fut>(
+    pid: Option<&str>,
+    interval: Option<f32>,
+    count: i32,
+    mut capture: F,
+) -> Result<Vec<Sample>, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Sample, String>>,
+{
+    let mut samples = vec![];
+    let mut count = if interval.is_none() { 1 } else { count };
+    let sleep = interval.unwrap_or(0.0);
+
+    loop {
+        let time = Some(SystemTime::now());
+        let tasks = pid.map(read_task_stats).unwrap_or_default();
+        let maps = pid.map(read_maps).unwrap_or_default();
+        let sample = capture().await?;
+        samples.push(Sample {
+            pid: pid.map(|s| s.to_owned()),
+            tasks,
+            maps,
+            time,
+            raw: sample.output.clone(),
+            raw_records: sample.records.clone(),
+            ..sample
+        });
+
+        count -= 1;
+        if count == 0 {
+            break;
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs_f32(sleep)).await;
+    }
+
+    Ok(samples)
+}
+
+/// parse_samples  -  parse threads of all samples.
+pub fn parse_samples(samples: &[Sample], format: InputFormat) -> Result<Vec<Thread>, String> {
+    let mut threads = vec![];
+    for sample in samples {
+        threads.extend(sample.threads(format)?);
+    }
+    Ok(threads)
+}
+
+/// What is printed of samples of a process.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub unique: bool,
+    /// Number of hot functions to rank.
+    pub hot: Option<usize>,
+    pub timeline: bool,
+    /// Archive samples are saved in.
+    pub save: Option<PathBuf>,
+}
+
+impl Report {
+    pub fn from_cli(cli: &Cli) -> Report {
+        Report {
+            unique: cli.unique_mode,
+            hot: cli.hot,
+            timeline: cli.timeline,
+            save: cli.save.as_deref().map(history_dir),
+        }
+    }
+}
+
+/// report_stuck  -  report of stuck threads of `samples`, empty if none.
+// Stuck threads come on top of what is asked: samples failing to parse are reported, and shown
+// anyway.
+fn report_stuck(samples: &[Sample], format: InputFormat, filter: &StackFilter) -> String {
+    match stuck_threads(samples, format, filter) {
+        Ok(threads) => print_stuck(&threads, samples.len()),
+        Err(err) => {
+            eprintln!("Failed to look for stuck threads: {err}");
+            String::new()
+        }
+    }
+}
+
+/// report_samples  -  print `samples` as asked by `report`.
+// Hot functions and timeline replace raw samples, but follow unique stacks. Stuck threads are
+// reported whenever several samples are taken.
+pub fn report_samples(
+    samples: Vec<Sample>,
+    format: InputFormat,
+    filter: &StackFilter,
+    report: Report,
+) -> Result<String, String> {
+    if let Some(dir) = &report.save {
+        archive_samples(dir, &samples, format);
+    }
+    if !report.unique && report.hot.is_none() && !report.timeline {
+        let many = samples.len() > 1;
+        let stuck = report_stuck(&samples, format, filter);
+        let mut output: Vec<String> = samples
+            .into_iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let stack = filter.demangle.stack(s.output);
+                match s.time {
+                    Some(time) if many => {
+                        format!("Sample {} at {} UTC:\n{stack}", i + 1, format_time(time))
+                    }
+                    _ => stack,
+                }
+            })
+            .collect();
+        if !stuck.is_empty() {
+            output.push(stuck);
+        }
+        return Ok(output.join("\n"));
+    }
+
+    let threads = parse_samples(&samples, format)?;
+    let mut parts = vec![];
+    if report.unique {
+        let unique = print_unique(StackGroups::from_threads(&threads, filter))?;
+        parts.push(format!("{}{}", unique, print_deadlocks(&threads)));
+    }
+    if let Some(limit) = report.hot {
+        parts.push(HotFrames::from_threads(&threads, filter).print(limit));
+    }
+    if report.timeline {
+        parts.push(Timeline::from_samples(&samples, format, filter)?.print());
+    }
+    let stuck = report_stuck(&samples, format, filter);
+    if !stuck.is_empty() {
+        parts.push(stuck);
+    }
+    Ok(parts.join("\n\n"))
+}
+
+#[test]
+fn test_sample_threads() {
+    let mut sample = Sample {
+        pid: Some("37746".to_owned()),
+        output: r#"
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6
+Thread 1 (Thread 0x7f29ce816740 (LWP 37746)):
+#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6
+"#
+        .to_owned(),
+        ..Default::default()
+    };
+    for (tid, comm, state) in [("37746", "test", 'S'), ("37748", "comm-name", 'D')] {
+        sample.tasks.insert(
+            tid.to_owned(),
+            TaskStat {
+                comm: comm.to_owned(),
+                state,
+                ..Default::default()
+            },
+        );
+    }
+
+    let threads = sample.threads(InputFormat::Gdb).unwrap();
+    assert_eq!(threads.len(), 2);
+    assert_eq!(threads[0].pid.as_deref(), Some("37746"));
+    // name reported by gdb wins
+    assert_eq!(threads[0].name.as_deref(), Some("worker"));
+    assert_eq!(threads[0].state, Some('D'));
+    assert_eq!(threads[1].name.as_deref(), Some("test"));
+    assert_eq!(threads[1].state, Some('S'));
+    assert_eq!(threads[0].lock_owner, None);
+
+    // threads built from records of GDB/MI are taken instead of output
+    let mut record = Thread::new(None, None, "37748");
+    record.name = Some("worker \"io\" (2)".to_owned());
+    record.lock_owner = Some("37746".to_owned());
+    sample.records = vec![record];
+    let threads = sample.threads(InputFormat::Gdb).unwrap();
+    assert_eq!(threads.len(), 1);
+    assert_eq!(threads[0].pid.as_deref(), Some("37746"));
+    assert_eq!(threads[0].name.as_deref(), Some("worker \"io\" (2)"));
+    assert_eq!(threads[0].state, Some('D'));
+    assert_eq!(threads[0].lock_owner.as_deref(), Some("37746"));
+}
+
+#[test]
+fn test_report_samples() {
+    let sample = |output: &str| Sample {
+        output: output.to_owned(),
+        ..Default::default()
+    };
+    // not a gdb backtrace, stuck threads cannot be looked for, samples are still shown
+    let samples = vec![sample("warning: no thread"), sample("warning: no thread")];
+    let output = report_samples(
+        samples,
+        InputFormat::Gdb,
+        &StackFilter::default(),
+        Report::default(),
+    )
+    .unwrap();
+    assert_eq!(output, "warning: no thread\nwarning: no thread");
+}
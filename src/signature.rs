@@ -0,0 +1,243 @@
+// Stack fingerprints and known issues.
+// A fingerprint is a hash of the functions of a stack, from the top: it does not change with
+// addresses, arguments, source locations, how frames are simplified, collapsed, filtered or
+// demangled, or depth of recursions, so it identifies a stack across hosts, builds and runs. A signature file maps
+// fingerprints or patterns to known issues.
+
+use colored::*;
+use regex::{Regex, RegexBuilder};
+
+use crate::{args::Cli, filter::collapse_frames, patterns, stack::function_name};
+
+/// fingerprint  -  FNV-1a hash of functions of frames of `stack`, one per line.
+// FNV-1a is stable across Rust versions and platforms, unlike the hasher of std.
+pub fn fingerprint(stack: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    normalize(stack).bytes().fold(OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// normalize  -  functions of frames of `stack`, one per line, as hashed and matched.
+// Lines other than frames, such as source lines or annotations, are skipped. Recursions are
+// collapsed until none is left, giving the same functions whether the stack is collapsed or not.
+pub fn normalize(stack: &str) -> String {
+    let mut functions: Vec<String> = stack
+        .lines()
+        .filter(|s| patterns::frame_function().is_match(s))
+        .map(|s| function_name(s).to_owned())
+        .collect();
+    loop {
+        let collapsed: Vec<String> = collapse_frames(functions.clone())
+            .iter()
+            .map(|f| function_name(f).to_owned())
+            .collect();
+        if collapsed.len() == functions.len() {
+            break;
+        }
+        functions = collapsed;
+    }
+    functions.iter().map(|f| format!("{f}\n")).collect()
+}
+
+/// What a signature matches stacks with.
+#[derive(Clone, Debug)]
+pub enum Matcher {
+    Fingerprint(u64),
+    // matched against functions of stacks, one per line
+    Pattern(Regex),
+}
+
+/// A known issue.
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub matcher: Matcher,
+    pub bug: String,
+    pub note: String,
+}
+
+/// parse_signatures  -  parse a signature file.
+// Each line is a fingerprint, or `re:REGEX` (without spaces, use `\s`), then a bug ID and an
+// optional note, separated by spaces. Empty lines and lines starting with `#` are skipped.
+pub fn parse_signatures(content: &str) -> Result<Vec<Signature>, String> {
+    let mut signatures = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((pattern, rest)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("line {}: expecting pattern and bug ID", i + 1));
+        };
+        let (bug, note) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest.trim_start(), ""));
+        let matcher = match pattern.strip_prefix("re:") {
+            Some(re) => RegexBuilder::new(re)
+                .multi_line(true)
+                .build()
+                .map(Matcher::Pattern)
+                .map_err(|err| format!("line {}: {err}", i + 1))?,
+            None => u64::from_str_radix(pattern, 16)
+                .map(Matcher::Fingerprint)
+                .map_err(|_| format!("line {}: invalid fingerprint '{pattern}'", i + 1))?,
+        };
+        signatures.push(Signature {
+            matcher,
+            bug: bug.to_owned(),
+            note: note.trim().to_owned(),
+        });
+    }
+    Ok(signatures)
+}
+
+/// Known issues stacks are matched against.
+#[derive(Clone, Debug, Default)]
+pub struct Signatures {
+    pub signatures: Vec<Signature>,
+    /// Show fingerprint of every stack, not only of those matching a known issue.
+    pub fingerprints: bool,
+}
+
+impl Signatures {
+    pub fn from_cli(cli: &Cli) -> Option<Signatures> {
+        if cli.signatures.is_none() && !cli.fingerprint {
+            return None;
+        }
+
+        let signatures = cli.signatures.as_ref().map_or(Ok(vec![]), |file| {
+            std::fs::read_to_string(file)
+                .map_err(|err| err.to_string())
+                .and_then(|content| parse_signatures(&content))
+                .map_err(|err| format!("{file}: {err}"))
+        });
+        let signatures = signatures.unwrap_or_else(|err| {
+            eprintln!("Known issues will not be matched: {err}");
+            vec![]
+        });
+        Some(Signatures {
+            signatures,
+            fingerprints: cli.fingerprint,
+        })
+    }
+
+    /// find  -  first known issue `stack` matches.
+    pub fn find(&self, stack: &str) -> Option<&Signature> {
+        let hash = fingerprint(stack);
+        let functions = normalize(stack);
+        self.signatures.iter().find(|s| match &s.matcher {
+            Matcher::Fingerprint(f) => *f == hash,
+            Matcher::Pattern(r) => r.is_match(&functions),
+        })
+    }
+
+    /// note  -  known issue `stack` matches, or its fingerprint, shown below the stack.
+    // `stack` is taken as demangled, before frames are filtered, so that options changing what is
+    // shown do not change what it matches.
+    pub fn note(&self, stack: &str) -> Option<String> {
+        let hash = fingerprint(stack);
+        match self.find(stack) {
+            Some(signature) => {
+                let mut line = format!("Known issue: {}", signature.bug.yellow().bold());
+                if !signature.note.is_empty() {
+                    line.push_str(&format!(" -- {}", signature.note));
+                }
+                Some(format!("{line}  [{hash:016x}]"))
+            }
+            None if self.fingerprints => Some(format!("Fingerprint: {hash:016x}")),
+            None => None,
+        }
+    }
+}
+
+#[test]
+fn test_fingerprint() {
+    let stack = "#0  0x00007f29ce8db9e7 in __lll_lock_wait () from /usr/lib64/libc.so.6\n\
+                 #1  0x00007f29ce8e2a55 in pthread_mutex_lock () from /usr/lib64/libc.so.6\n\
+                 #2  0x000055723be89162 in flush (fd=3) at store.c:42\n";
+    // same functions, other addresses, arguments and simplification
+    let other = "#0  0x00007f0000000001 __lll_lock_wait\n\
+                 #1  0x00007f0000000002 pthread_mutex_lock\n\
+                 #2  0x0000560000000003 flush at store.c:40\n\
+                       40  write(fd, buf, n);\n";
+    assert_eq!(
+        normalize(stack),
+        "__lll_lock_wait\npthread_mutex_lock\nflush\n"
+    );
+    assert_eq!(fingerprint(stack), fingerprint(other));
+    assert_ne!(fingerprint(stack), fingerprint("#0  0x1 flush\n"));
+    // FNV-1a of empty input is its offset basis
+    assert_eq!(fingerprint(""), 0xcbf29ce484222325);
+
+    // collapsed by --collapse, or recursing deeper
+    let recursive = "#0  0x00007f0000000001 lex\n\
+                     #1  0x00007f0000000002 parse\n\
+                     #2  0x00007f0000000002 parse\n\
+                     #3  0x00007f0000000002 parse\n\
+                     #4  0x00007f0000000003 main\n";
+    let frames: Vec<String> = recursive.lines().map(|s| s.to_owned()).collect();
+    let collapsed = collapse_frames(frames).join("\n");
+    assert!(collapsed.contains("parse  [x 3]"));
+    assert_eq!(normalize(&collapsed), "lex\nparse\nmain\n");
+    assert_eq!(fingerprint(&collapsed), fingerprint(recursive));
+    let deeper = recursive.replace(
+        "#3  0x00007f0000000002 parse\n",
+        "#3  0x2 parse\n#3  0x2 parse\n",
+    );
+    assert_eq!(fingerprint(&deeper), fingerprint(recursive));
+    // frames without symbol are kept, each may be another function
+    let unknown = "#0  0x1 in ?? ()\n#1  0x2 in ?? ()\n#2  0x3 in main ()\n";
+    assert_eq!(normalize(unknown), "??\n??\nmain\n");
+}
+
+#[test]
+fn test_signatures() {
+    let stack = "#0  0x00007f29ce8db9e7 __lll_lock_wait\n\
+                 #1  0x00007f29ce8e2a55 pthread_mutex_lock\n\
+                 #2  0x000055723be89162 flush\n";
+    let content = format!(
+        "# known issues\n\
+         \n\
+         {:016x}  BUG-1234  flush blocked by rotate\n\
+         re:^pthread_mutex_lock\\n(rotate|compact)$  BUG-99\n",
+        fingerprint(stack)
+    );
+    let signatures = Signatures {
+        signatures: parse_signatures(&content).unwrap(),
+        fingerprints: false,
+    };
+    assert_eq!(signatures.signatures.len(), 2);
+    assert_eq!(signatures.find(stack).unwrap().bug, "BUG-1234");
+    let compact = "#0  0x1 __lll_lock_wait\n#1  0x2 pthread_mutex_lock\n#2  0x3 compact\n";
+    let found = signatures.find(compact).unwrap();
+    assert_eq!((found.bug.as_str(), found.note.as_str()), ("BUG-99", ""));
+    assert!(signatures.find("#0  0x1 __poll\n").is_none());
+
+    assert!(parse_signatures("xyz BUG-1").is_err());
+    assert!(parse_signatures("re:( BUG-1").is_err());
+    assert!(parse_signatures("abcdef").is_err());
+
+    let note = signatures.note(stack).unwrap();
+    assert!(note.starts_with("Known issue: "));
+    assert!(note.contains("BUG-1234"));
+    assert!(note.ends_with(&format!(
+        " -- flush blocked by rotate  [{:016x}]",
+        fingerprint(stack)
+    )));
+    assert_eq!(signatures.note("#0  0x1 __poll\n"), None);
+
+    let all = Signatures {
+        fingerprints: true,
+        ..signatures
+    };
+    assert_eq!(
+        all.note("#0  0x1 __poll\n"),
+        Some(format!(
+            "Fingerprint: {:016x}",
+            fingerprint("#0  0x1 __poll\n")
+        ))
+    );
+}
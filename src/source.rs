@@ -0,0 +1,140 @@
+// Source snippets shown below top frames of unique stacks, for frames having `at file:line`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{args::Cli, patterns};
+
+/// Number of frames, from the top of each stack, that source is shown for.
+const SOURCE_FRAMES: usize = 3;
+
+/// How source of frames is shown.
+#[derive(Clone, Debug, Default)]
+pub struct SourceView {
+    /// Number of lines shown around line of frame.
+    pub lines: usize,
+    /// (from, to) prefixes of source paths, for builds done elsewhere.
+    pub maps: Vec<(String, String)>,
+}
+
+impl SourceView {
+    pub fn from_cli(cli: &Cli) -> Option<SourceView> {
+        cli.source.filter(|n| *n > 0).map(|lines| SourceView {
+            lines,
+            maps: cli.source_maps.clone(),
+        })
+    }
+
+    /// local_path  -  path of `file` on this host, after remapping.
+    pub fn local_path(&self, file: &str) -> PathBuf {
+        self.maps
+            .iter()
+            .find_map(|(from, to)| {
+                file.strip_prefix(from.as_str())
+                    .map(|rest| Path::new(to).join(rest.trim_start_matches('/')))
+            })
+            .unwrap_or_else(|| PathBuf::from(file))
+    }
+
+    /// snippet  -  lines around `line` of `content`, the line itself marked with `>`.
+    pub fn snippet(&self, content: &[String], line: usize) -> Option<Vec<String>> {
+        if line == 0 || line > content.len() {
+            return None;
+        }
+
+        // keep `lines` lines near start and end of file too
+        let first = line.saturating_sub(self.lines.saturating_sub(1) / 2).max(1);
+        let last = (first + self.lines - 1).min(content.len());
+        let first = (last + 1).saturating_sub(self.lines).max(1);
+        Some(
+            (first..=last)
+                .map(|n| {
+                    let mark = if n == line { '>' } else { ' ' };
+                    format!("    {mark} {n:>5}  {}", content[n - 1])
+                })
+                .collect(),
+        )
+    }
+
+    /// annotate  -  add source snippets below top frames of `stack`.
+    // Files are read once for all stacks, through `cache`.
+    fn annotate(&self, stack: &str, cache: &mut HashMap<PathBuf, Option<Vec<String>>>) -> String {
+        let mut result = String::with_capacity(stack.len());
+        for (i, frame) in stack.lines().enumerate() {
+            result.push_str(frame);
+            result.push('\n');
+            if i >= SOURCE_FRAMES {
+                continue;
+            }
+
+            let Some(m) = patterns::frame_location().captures(frame) else {
+                continue;
+            };
+            let path = self.local_path(m.name("file").unwrap().as_str());
+            let line = m.name("line").unwrap().as_str().parse().unwrap_or(0);
+            let content = cache.entry(path).or_insert_with_key(|path| {
+                std::fs::read_to_string(path)
+                    .ok()
+                    .map(|s| s.lines().map(|l| l.to_owned()).collect())
+            });
+            if let Some(snippet) = content.as_ref().and_then(|c| self.snippet(c, line)) {
+                result.push_str(&snippet.join("\n"));
+                result.push('\n');
+            }
+        }
+
+        result
+    }
+
+    /// annotate_groups  -  add source snippets to stacks of `groups`.
+    pub fn annotate_groups(&self, groups: &mut [(String, Vec<String>)]) {
+        let mut cache = HashMap::new();
+        for (stack, _) in groups.iter_mut() {
+            *stack = self.annotate(stack, &mut cache);
+        }
+    }
+}
+
+#[test]
+fn test_source_view() {
+    let dir = std::env::temp_dir().join(format!("cs-source-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let content: String = (1..=20).map(|n| format!("line {n}\n")).collect();
+    std::fs::write(dir.join("test.c"), content).unwrap();
+
+    let view = SourceView {
+        lines: 3,
+        maps: vec![("/build/src".to_owned(), dir.to_string_lossy().to_string())],
+    };
+    assert_eq!(view.local_path("/build/src/test.c"), dir.join("test.c"));
+    assert_eq!(view.local_path("test.c"), PathBuf::from("test.c"));
+
+    let mut groups = vec![(
+        "#0  0x00007f29ce8db9e7 clock_nanosleep\n\
+         #1  0x000055723be89162 func2 at /build/src/test.c:5\n\
+         #2  0x000055723be8917d func1 at /build/src/missing.c:10\n"
+            .to_owned(),
+        vec!["1".to_owned()],
+    )];
+    view.annotate_groups(&mut groups);
+    assert_eq!(
+        groups[0].0.lines().collect::<Vec<_>>(),
+        vec![
+            "#0  0x00007f29ce8db9e7 clock_nanosleep",
+            "#1  0x000055723be89162 func2 at /build/src/test.c:5",
+            "          4  line 4",
+            "    >     5  line 5",
+            "          6  line 6",
+            "#2  0x000055723be8917d func1 at /build/src/missing.c:10",
+        ]
+    );
+
+    let lines: Vec<String> = (1..=20).map(|n| format!("{n}")).collect();
+    assert_eq!(view.snippet(&lines, 1).unwrap().len(), 3);
+    assert_eq!(view.snippet(&lines, 20).unwrap()[0], "         18  18");
+    assert!(view.snippet(&lines, 21).is_none());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
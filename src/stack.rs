@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::patterns;
+
+/// function_name  -  name of function called in `frame`, without address, arguments or location.
+// Returns "??" for frames without symbol. Repeat counts added by `collapse_frames` are dropped.
+pub fn function_name(frame: &str) -> &str {
+    let frame = frame.find("  [x ").map_or(frame, |i| &frame[..i]);
+    let Some(m) = patterns::frame_function().captures(frame) else {
+        return frame.trim();
+    };
+
+    let func = m.name("func").unwrap().as_str();
+    let end = [" (", " from ", " at "]
+        .iter()
+        .filter_map(|sep| func.find(sep))
+        .min()
+        .unwrap_or(func.len());
+    match func[..end].trim() {
+        "" => "??",
+        name => name,
+    }
+}
+
+/// label_flags  -  whether labels of `threads` are prefixed with source, and with pid.
+// Sources tell threads apart once they come from several files, pids once they come from several
+// processes.
+pub fn label_flags<'a>(threads: impl IntoIterator<Item = &'a Thread>) -> (bool, bool) {
+    let mut processes = HashSet::new();
+    let mut sources = HashSet::new();
+    for thread in threads {
+        processes.insert((&thread.source, &thread.pid));
+        sources.insert(&thread.source);
+    }
+    (sources.len() > 1, processes.len() > 1)
+}
+
+/// Arguments and local variables of a frame, as (name, value) pairs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrameVars {
+    pub args: Vec<(String, String)>,
+    pub locals: Vec<(String, String)>,
+}
+
+/// A thread and its call stack, as parsed from a stack dump.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Thread {
+    /// File this thread is read from, None if it comes from stdin or a running process.
+    pub source: Option<String>,
+    /// Process this thread belongs to, if known.
+    pub pid: Option<String>,
+    pub tid: String,
+    /// Thread name, reported by gdb or read from /proc.
+    pub name: Option<String>,
+    /// Scheduler state read from /proc, for threads of running processes.
+    pub state: Option<char>,
+    pub frames: Vec<String>,
+    /// Variables of frames keyed by frame level, captured by gdb `bt full` only.
+    pub vars: BTreeMap<usize, FrameVars>,
+    /// Thread owning the mutex this thread waits for, read by the gdb backend.
+    pub lock_owner: Option<String>,
+}
+
+impl Thread {
+    pub fn new(source: Option<&str>, pid: Option<&str>, tid: &str) -> Thread {
+        Thread {
+            source: source.map(|s| s.to_owned()),
+            pid: pid.map(|s| s.to_owned()),
+            tid: tid.to_owned(),
+            name: None,
+            state: None,
+            frames: vec![],
+            vars: BTreeMap::new(),
+            lock_owner: None,
+        }
+    }
+
+    /// stack  -  call stack as text, one frame per line.
+    pub fn stack(&self) -> String {
+        let mut stack = String::new();
+        for frame in &self.frames {
+            stack.push_str(frame);
+            stack.push('\n');
+        }
+        stack
+    }
+
+    /// key  -  call stack used to tell if two threads are identical.
+    // Addresses differ between processes (ASLR, different builds...), thus they are dropped when
+    // comparing threads of different processes, keeping frame level and function only.
+    pub fn key(&self, ignore_address: bool) -> String {
+        if !ignore_address {
+            return self.stack();
+        }
+
+        let r_match_entry = patterns::frame_function();
+        let mut key = String::new();
+        for frame in &self.frames {
+            match r_match_entry.captures(frame) {
+                Some(m) => {
+                    let func = m.name("func").unwrap().as_str();
+                    key.push_str(&format!(
+                        "#{} {}\n",
+                        m.name("level").unwrap().as_str(),
+                        if func.is_empty() { "??" } else { func }
+                    ));
+                }
+                None => {
+                    key.push_str(frame);
+                    key.push('\n');
+                }
+            }
+        }
+        key
+    }
+
+    /// label  -  name of this thread when listed in a group of threads.
+    // Thread ID alone is ambiguous once threads from several processes or files are merged, thus
+    // it is prefixed with source and pid when asked to.
+    pub fn label(&self, with_source: bool, with_pid: bool) -> String {
+        let mut parts = vec![];
+        if with_source {
+            parts.push(self.source.as_deref().unwrap_or("-"));
+        }
+        if with_pid {
+            if let Some(pid) = &self.pid {
+                parts.push(pid);
+            }
+        }
+        parts.push(&self.tid);
+        parts.join(":")
+    }
+
+    /// title  -  pid:tid of this thread followed by its name, when following it over samples.
+    pub fn title(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({name})", self.label(false, true)),
+            None => self.label(false, true),
+        }
+    }
+}
+
+#[test]
+fn test_thread_label() {
+    let mut thread = Thread::new(Some("host-a.txt"), Some("1234"), "1240");
+    thread
+        .frames
+        .push("#0  0x00007f83ddc5363f __poll".to_owned());
+    thread.frames.push("#1  0x00007f83de32a8d7".to_owned());
+
+    assert_eq!(
+        thread.stack(),
+        "#0  0x00007f83ddc5363f __poll\n#1  0x00007f83de32a8d7\n"
+    );
+    assert_eq!(thread.label(false, false), "1240");
+    assert_eq!(thread.label(false, true), "1234:1240");
+    assert_eq!(thread.label(true, true), "host-a.txt:1234:1240");
+
+    let thread = Thread::new(None, None, "37747");
+    assert_eq!(thread.label(true, true), "-:37747");
+}
+
+#[test]
+fn test_thread_key() {
+    let mut t1 = Thread::new(None, Some("100"), "100");
+    t1.frames.push("#0  0x00007f83ddc5363f __poll".to_owned());
+    t1.frames.push("#1  0x00007f83de32a8d7".to_owned());
+    let mut t2 = Thread::new(None, Some("200"), "200");
+    t2.frames
+        .push("#0  0x00007f0000c5363f in __poll".to_owned());
+    t2.frames.push("#1  0x00007f0000000001".to_owned());
+
+    assert_ne!(t1.key(false), t2.key(false));
+    assert_eq!(t1.key(true), t2.key(true));
+    assert_eq!(t1.key(true), "#0 __poll\n#1 ??\n");
+}
+
+#[test]
+fn test_function_name() {
+    for (frame, name) in [
+        ("#0  0x00007f83ddc5363f __poll", "__poll"),
+        ("#1  0x00007f83de32a8d7", "??"),
+        (
+            "#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6",
+            "clock_nanosleep",
+        ),
+        (
+            "#6  0x000055723be891af in main (argc=1, argv=0x7ffec118b6f8) at test.c:19",
+            "main",
+        ),
+        ("#4  Foo::bar (this=0x0) at foo.cc:10", "Foo::bar"),
+        ("#3  0x000055723be89162 func2", "func2"),
+        (
+            "#7  0x00007f29ce83f320 in ?? () from /usr/lib64/libc.so.6",
+            "??",
+        ),
+    ] {
+        assert_eq!(function_name(frame), name, "{frame}");
+    }
+}
@@ -0,0 +1,212 @@
+// Stuck threads: threads found in the same stack in every sample, without using any CPU time in
+// between. Threads idle in an event loop look alike, they are told apart by class of their stack.
+
+use colored::*;
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    classify::{classify, stack_class, ThreadClass},
+    filter::StackFilter,
+    format::InputFormat,
+    sample::Sample,
+    stack::{function_name, Thread},
+};
+
+/// A thread unchanged over all samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StuckThread {
+    pub title: String,
+    pub class: ThreadClass,
+    /// Function at the top of its stack.
+    pub function: String,
+    /// Time between the first and the last sample.
+    pub duration: Duration,
+}
+
+/// A thread followed over samples.
+struct Tracked {
+    thread: Thread,
+    key: String,
+    /// CPU time in clock ticks, None if status of the thread is unknown.
+    cpu: Option<u64>,
+    seen: usize,
+    unchanged: bool,
+    duration: Duration,
+}
+
+/// stuck_threads  -  threads of `samples` passing `filter` unchanged in all of them, ordered as in
+/// the first sample. Needs two samples at least.
+// A thread is unchanged if its stack is the same (addresses aside) and its CPU time did not
+// advance, when known: a thread looping quickly may be caught in the same stack several times.
+pub fn stuck_threads(
+    samples: &[Sample],
+    format: InputFormat,
+    filter: &StackFilter,
+) -> Result<Vec<StuckThread>, String> {
+    if samples.len() < 2 {
+        return Ok(vec![]);
+    }
+
+    let first = samples[0].time;
+    let mut tracked: Vec<Tracked> = vec![];
+    let mut index: HashMap<(Option<String>, String), usize> = HashMap::new();
+    for sample in samples {
+        for thread in sample.threads(format)? {
+            let Some(thread) = filter.apply(thread) else {
+                continue;
+            };
+
+            let key = thread.key(true);
+            let cpu = sample
+                .tasks
+                .get(&thread.tid)
+                .map(|stat| stat.utime + stat.stime);
+            let duration = match (first, sample.time) {
+                (Some(first), Some(time)) => time.duration_since(first).unwrap_or_default(),
+                _ => Duration::ZERO,
+            };
+            match index.get(&(thread.pid.clone(), thread.tid.clone())) {
+                Some(i) => {
+                    let t = &mut tracked[*i];
+                    t.unchanged &= t.key == key && t.cpu == cpu;
+                    t.seen += 1;
+                    t.duration = duration;
+                }
+                None => {
+                    index.insert((thread.pid.clone(), thread.tid.clone()), tracked.len());
+                    tracked.push(Tracked {
+                        thread,
+                        key,
+                        cpu,
+                        seen: 1,
+                        unchanged: true,
+                        duration,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(tracked
+        .into_iter()
+        .filter(|t| t.unchanged && t.seen == samples.len())
+        .map(|t| StuckThread {
+            title: t.thread.title(),
+            class: classify(stack_class(&t.thread.stack()), t.thread.state),
+            function: t
+                .thread
+                .frames
+                .first()
+                .map_or("??", |f| function_name(f))
+                .to_owned(),
+            duration: t.duration,
+        })
+        .collect())
+}
+
+/// print_stuck  -  report of stuck threads, with threads merely idle listed apart, empty if none.
+pub fn print_stuck(threads: &[StuckThread], samples: usize) -> String {
+    let (idle, stuck): (Vec<&StuckThread>, Vec<&StuckThread>) = threads
+        .iter()
+        .partition(|t| matches!(t.class, ThreadClass::Idle | ThreadClass::Sleeping));
+
+    let mut lines = vec![];
+    if !stuck.is_empty() {
+        lines.push(format!(
+            "Stuck threads, same stack and no CPU time in all {samples} samples:"
+        ));
+        let width = stuck.iter().map(|t| t.title.len()).max().unwrap();
+        for t in stuck {
+            lines.push(format!(
+                "  {}  {:>7.2}s  {:<8}  {}",
+                format!("{:<width$}", t.title).red(),
+                t.duration.as_secs_f32(),
+                t.class.name(),
+                t.function
+            ));
+        }
+    }
+    if !idle.is_empty() {
+        let titles: Vec<&str> = idle.iter().map(|t| t.title.as_str()).collect();
+        lines.push(format!(
+            "Idle threads, unchanged in all {samples} samples: {}",
+            titles.join(", ")
+        ));
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn test_stuck_threads() {
+    use crate::procfs::TaskStat;
+    use std::time::UNIX_EPOCH;
+
+    let sample = |secs: u64, output: &str, cpu: &[(&str, u64)]| Sample {
+        pid: Some("37746".to_owned()),
+        output: output.to_owned(),
+        tasks: cpu
+            .iter()
+            .map(|(tid, utime)| {
+                let stat = TaskStat {
+                    state: 'S',
+                    utime: *utime,
+                    ..Default::default()
+                };
+                (tid.to_string(), stat)
+            })
+            .collect(),
+        time: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        ..Default::default()
+    };
+    let stacks = r#"
+Thread 4 (Thread 0x7f29ce816740 (LWP 37749) "busy"):
+#0  0x00007f29ce8db9e7 in compute () at compute.c:10
+Thread 3 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e7 in __lll_lock_wait () from /usr/lib64/libc.so.6
+#1  0x00007f29ce8e2a55 in pthread_mutex_lock () from /usr/lib64/libc.so.6
+Thread 2 (Thread 0x7f29ce816740 (LWP 37747) "loop"):
+#0  0x00007f29ce8db9e7 in __poll () from /usr/lib64/libc.so.6
+Thread 1 (Thread 0x7f29ce816740 (LWP 37746) "main"):
+#0  0x00007f29ce8db9e7 in recv () from /usr/lib64/libc.so.6
+"#;
+    let moved = stacks.replace("in recv ()", "in write ()");
+    let samples = vec![
+        sample(100, stacks, &[("37749", 10), ("37748", 5), ("37747", 1)]),
+        sample(101, stacks, &[("37749", 20), ("37748", 5), ("37747", 1)]),
+        sample(
+            103,
+            &moved,
+            &[("37749", 30), ("37748", 5), ("37747", 1), ("37746", 0)],
+        ),
+    ];
+
+    let filter = StackFilter::default();
+    let stuck = stuck_threads(&samples, InputFormat::Gdb, &filter).unwrap();
+    assert_eq!(
+        stuck,
+        vec![
+            StuckThread {
+                title: "37746:37748 (worker)".to_owned(),
+                class: ThreadClass::Lock,
+                function: "__lll_lock_wait".to_owned(),
+                duration: Duration::from_secs(3),
+            },
+            StuckThread {
+                title: "37746:37747 (loop)".to_owned(),
+                class: ThreadClass::Idle,
+                function: "__poll".to_owned(),
+                duration: Duration::from_secs(3),
+            },
+        ]
+    );
+    assert!(stuck_threads(&samples[..1], InputFormat::Gdb, &filter)
+        .unwrap()
+        .is_empty());
+
+    let output = print_stuck(&stuck, samples.len());
+    assert!(output.starts_with("Stuck threads, same stack and no CPU time in all 3 samples:\n"));
+    assert!(output.contains("37746:37748 (worker)"));
+    assert!(output.contains("     3.00s  lock      __lll_lock_wait\n"));
+    assert!(output.ends_with("Idle threads, unchanged in all 3 samples: 37746:37747 (loop)"));
+    assert_eq!(print_stuck(&[], 3), "");
+}
@@ -0,0 +1,389 @@
+// Offline symbolization of frames without symbol, e.g. `#19 0x00007f83ddb902e0` of eu-stack or
+// `#7  0x00007f29ce83f320 in ?? () from /usr/lib64/libc.so.6` of gdb.
+// An address is located in memory map of the process, then in the mapped file, and resolved with
+// symbols and debug info of the executable or library, or of its debug file found by build-id.
+
+use addr2line::Loader;
+use object::{
+    elf,
+    read::elf::{ElfFile64, FileHeader, ProgramHeader},
+    Endianness, Object, ObjectSegment, ReadCache,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use crate::{args::Cli, demangle::demangle_symbol, patterns, sample::Sample};
+
+/// A file mapped into address space of a process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    /// Offset in file of `start`
+    pub offset: u64,
+    pub path: String,
+}
+
+/// parse_maps  -  parse content of `/proc/<pid>/maps`, keeping mappings of files only.
+pub fn parse_maps(content: &str) -> Vec<Mapping> {
+    content
+        .lines()
+        .filter_map(|line| {
+            // address perms offset dev inode path
+            let mut fields = line.splitn(6, char::is_whitespace);
+            let (start, end) = fields.next()?.split_once('-')?;
+            let offset = fields.nth(1)?;
+            let path = fields.nth(2)?.trim();
+            if !path.starts_with('/') {
+                return None;
+            }
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: path.trim_end_matches(" (deleted)").to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// read_maps  -  read memory map of process `pid`, empty if the process is gone or not readable.
+pub fn read_maps(pid: &str) -> Vec<Mapping> {
+    std::fs::read_to_string(format!("/proc/{pid}/maps"))
+        .map(|content| parse_maps(&content))
+        .unwrap_or_default()
+}
+
+/// parse_nt_file  -  parse description of `NT_FILE` note of a 64 bits core file.
+// count, page size, `count` of (start, end, offset in pages), then `count` of NUL terminated paths.
+// Notes of truncated or corrupted cores may hold any count: it cannot exceed what `desc` holds.
+fn parse_nt_file(desc: &[u8], little_endian: bool) -> Option<Vec<Mapping>> {
+    let word = |i: usize| -> Option<u64> {
+        let bytes: [u8; 8] = desc.get(i * 8..i * 8 + 8)?.try_into().ok()?;
+        Some(if little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    };
+
+    let count = usize::try_from(word(0)?).ok()?;
+    if count > desc.len() / 24 {
+        return None;
+    }
+    let page_size = word(1)?;
+    let paths_start = count.checked_mul(3)?.checked_add(2)?.checked_mul(8)?;
+    let mut paths = desc.get(paths_start..)?.split(|c| *c == 0);
+    (0..count)
+        .map(|i| {
+            Some(Mapping {
+                start: word(2 + i * 3)?,
+                end: word(3 + i * 3)?,
+                offset: word(4 + i * 3)?.checked_mul(page_size)?,
+                path: String::from_utf8_lossy(paths.next()?).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// read_core_maps  -  read memory map of process from `NT_FILE` note of core file `core`.
+// Only notes are read, core files can be huge.
+pub fn read_core_maps(core: &str) -> Result<Vec<Mapping>, String> {
+    let file = File::open(core).map_err(|err| err.to_string())?;
+    let cache = ReadCache::new(file);
+    let elf = ElfFile64::<Endianness, _>::parse(&cache).map_err(|err| err.to_string())?;
+    let endian = elf.endian();
+    for header in elf.elf_program_headers() {
+        if header.p_type(endian) != elf::PT_NOTE {
+            continue;
+        }
+        let Ok(Some(mut notes)) = header.notes(endian, elf.data()) else {
+            continue;
+        };
+        while let Ok(Some(note)) = notes.next() {
+            if note.name() == elf::ELF_NOTE_CORE && note.n_type(endian) == elf::NT_FILE {
+                return parse_nt_file(note.desc(), elf.elf_header().is_little_endian())
+                    .ok_or_else(|| format!("malformed NT_FILE note in {core}"));
+            }
+        }
+    }
+
+    Err(format!("no NT_FILE note in {core}"))
+}
+
+/// maps_from_cli  -  memory map for stacks not read from running processes.
+// Taken from `--maps`, or from the core file; only needed when there are symbols to resolve with,
+// given by `--symbols` or by the executable of the core file.
+pub fn maps_from_cli(cli: &Cli) -> Vec<Mapping> {
+    if cli.symbols.is_empty() && cli.executable.is_none() {
+        return vec![];
+    }
+
+    let result = if let Some(maps) = &cli.maps {
+        std::fs::read_to_string(maps)
+            .map(|content| parse_maps(&content))
+            .map_err(|err| format!("failed to read {maps}: {err}"))
+    } else if let Some(core) = &cli.core {
+        read_core_maps(core)
+    } else {
+        Ok(vec![])
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("Frames will not be resolved: {err}");
+        vec![]
+    })
+}
+
+/// build_id_path  -  path of debug file of `build_id` under debug directory `dir`.
+pub fn build_id_path(dir: &Path, build_id: &[u8]) -> Option<PathBuf> {
+    let (first, rest) = build_id.split_first()?;
+    let rest: String = rest.iter().map(|b| format!("{b:02x}")).collect();
+    Some(
+        dir.join(".build-id")
+            .join(format!("{first:02x}"))
+            .join(format!("{rest}.debug")),
+    )
+}
+
+/// Function and source location an address is resolved to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbol {
+    pub function: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// An executable or library, and its symbols.
+struct Binary {
+    // (offset in file, size in file, virtual address) of loaded segments
+    segments: Vec<(u64, u64, u64)>,
+    loader: Loader,
+}
+
+/// Resolves addresses with executables, libraries and debug directories given by user.
+pub struct Symbolizer {
+    paths: Vec<PathBuf>,
+    // keyed by path of mapped file, None if it can not be loaded
+    binaries: HashMap<String, Option<Binary>>,
+}
+
+impl Symbolizer {
+    pub fn new(paths: &[PathBuf]) -> Symbolizer {
+        Symbolizer {
+            paths: paths.to_vec(),
+            binaries: HashMap::new(),
+        }
+    }
+
+    /// find_binary  -  local copy of file mapped from `path`.
+    // Files given by user are preferred, matched by path or file name, as stacks may come from
+    // another host.
+    fn find_binary(&self, path: &str) -> Option<PathBuf> {
+        let name = Path::new(path).file_name();
+        self.paths
+            .iter()
+            .filter(|p| p.is_file())
+            .find(|p| p.as_path() == Path::new(path) || p.file_name() == name)
+            .cloned()
+            .or_else(|| Path::new(path).is_file().then(|| PathBuf::from(path)))
+    }
+
+    fn load(&self, path: &str) -> Option<Binary> {
+        let binary = self.find_binary(path)?;
+        let data = std::fs::read(&binary).ok()?;
+        let object = object::File::parse(&*data).ok()?;
+        let segments = object
+            .segments()
+            .map(|s| (s.file_range().0, s.file_range().1, s.address()))
+            .collect();
+        let debug_file = object.build_id().ok().flatten().and_then(|id| {
+            self.paths
+                .iter()
+                .filter(|p| p.is_dir())
+                .filter_map(|dir| build_id_path(dir, id))
+                .find(|p| p.is_file())
+        });
+
+        let loader = debug_file
+            .and_then(|f| Loader::new(f).ok())
+            .or_else(|| Loader::new(&binary).ok())?;
+        Some(Binary { segments, loader })
+    }
+
+    /// resolve  -  resolve `address` in a process of memory map `maps`.
+    pub fn resolve(&mut self, maps: &[Mapping], address: u64) -> Option<Symbol> {
+        let mapping = maps
+            .iter()
+            .find(|m| m.start <= address && address < m.end)?;
+        if !self.binaries.contains_key(&mapping.path) {
+            let binary = self.load(&mapping.path);
+            self.binaries.insert(mapping.path.clone(), binary);
+        }
+        let binary = self.binaries.get(&mapping.path)?.as_ref()?;
+
+        let offset = address - mapping.start + mapping.offset;
+        let (file_offset, _, vaddr) = binary
+            .segments
+            .iter()
+            .find(|(start, size, _)| *start <= offset && offset < start + size)?;
+        let probe = offset - file_offset + vaddr;
+
+        let loader = &binary.loader;
+        let mut symbol = Symbol::default();
+        if let Some(frame) = loader.find_frames(probe).ok()?.next().ok().flatten() {
+            if let Some(name) = frame.function.as_ref().and_then(|f| f.raw_name().ok()) {
+                symbol.function = demangle_symbol(&name).unwrap_or(name.to_string());
+            }
+            if let Some(location) = frame.location {
+                symbol.file = location.file.map(|f| f.to_owned());
+                symbol.line = location.line;
+            }
+        }
+        if symbol.function.is_empty() {
+            let name = loader.find_symbol(probe)?;
+            symbol.function = demangle_symbol(name).unwrap_or(name.to_owned());
+        }
+
+        Some(symbol)
+    }
+
+    /// line  -  resolve frame `s` if it has no symbol, otherwise returns it as is.
+    // Frames other than the innermost one hold return addresses, which may belong to the next
+    // line, or even the next function, thus the address of the call instruction is resolved.
+    pub fn line(&mut self, maps: &[Mapping], s: &str) -> String {
+        let Some(m) = patterns::unresolved_frame().captures(s) else {
+            return s.to_owned();
+        };
+        let prefix = m.name("prefix").unwrap().as_str();
+        let addr = m.name("addr").unwrap().as_str();
+        let Ok(address) = u64::from_str_radix(&addr[2..], 16) else {
+            return s.to_owned();
+        };
+        let probe = if m.name("level").unwrap().as_str() == "0" {
+            address
+        } else {
+            address.saturating_sub(1)
+        };
+        let Some(symbol) = self.resolve(maps, probe) else {
+            return s.to_owned();
+        };
+
+        let location = match (&symbol.file, symbol.line) {
+            (Some(file), Some(line)) => format!(" at {file}:{line}"),
+            (Some(file), None) => format!(" at {file}"),
+            _ => "".to_owned(),
+        };
+        if m.name("gdb").is_some() {
+            // keep `from library` of gdb when location is unknown
+            let from = m.name("from").map_or("", |m| m.as_str());
+            let location = if location.is_empty() { from } else { &location };
+            format!("{prefix}{addr} in {} (){location}", symbol.function)
+        } else {
+            format!("{prefix}{addr} {}{location}", symbol.function)
+        }
+    }
+
+    /// stack  -  resolve every frame of `input` without symbol.
+    pub fn stack(&mut self, maps: &[Mapping], input: String) -> String {
+        input
+            .split('\n')
+            .map(|s| self.line(maps, s))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// symbolize_samples  -  resolve frames without symbol in `samples`, with `paths` given by user.
+// Samples of running processes carry their own memory map, `maps` is used for others.
+pub fn symbolize_samples(samples: &mut [Sample], paths: &[PathBuf], maps: &[Mapping]) {
+    if paths.is_empty() {
+        return;
+    }
+
+    let mut symbolizer = Symbolizer::new(paths);
+    for sample in samples.iter_mut() {
+        let maps = if sample.maps.is_empty() {
+            maps
+        } else {
+            &sample.maps
+        };
+        sample.output = symbolizer.stack(maps, std::mem::take(&mut sample.output));
+        for frame in sample.records.iter_mut().flat_map(|t| t.frames.iter_mut()) {
+            *frame = symbolizer.line(maps, frame);
+        }
+    }
+}
+
+#[test]
+fn test_parse_maps() {
+    let maps = parse_maps(
+        "55723be88000-55723be89000 r--p 00000000 fd:01 1835143                    /tmp/test
+55723be89000-55723be8a000 r-xp 00001000 fd:01 1835143                    /tmp/test
+7f29ce800000-7f29ce828000 r--p 00000000 fd:01 1705418                    /usr/lib64/libc.so.6
+7f29ce9f4000-7f29ce9f6000 rw-p 00000000 00:00 0
+7f29cea00000-7f29cea01000 r-xp 00002000 fd:01 42                         /tmp/my lib.so (deleted)
+7ffec116b000-7ffec118c000 rw-p 00000000 00:00 0                          [stack]",
+    );
+    assert_eq!(maps.len(), 4);
+    assert_eq!(
+        maps[1],
+        Mapping {
+            start: 0x55723be89000,
+            end: 0x55723be8a000,
+            offset: 0x1000,
+            path: "/tmp/test".to_owned()
+        }
+    );
+    assert_eq!(maps[3].path, "/tmp/my lib.so");
+
+    let mut desc = vec![];
+    for word in [2_u64, 4096, 0x1000, 0x3000, 0, 0x5000, 0x6000, 2] {
+        desc.extend(word.to_le_bytes());
+    }
+    desc.extend(b"/bin/a\0/lib/b.so\0");
+    let maps = parse_nt_file(&desc, true).unwrap();
+    assert_eq!(maps.len(), 2);
+    assert_eq!(maps[1].offset, 0x2000);
+    assert_eq!(maps[1].path, "/lib/b.so");
+    assert!(parse_nt_file(&desc[..40], true).is_none());
+    // count of a corrupted note, overflowing the offset of paths
+    let mut corrupted = desc.clone();
+    corrupted[..8].copy_from_slice(&(u64::MAX / 3).to_le_bytes());
+    assert!(parse_nt_file(&corrupted, true).is_none());
+    assert!(read_core_maps("/proc/self/exe").is_err());
+
+    assert_eq!(
+        build_id_path(Path::new("/debug"), &[0xab, 0xcd, 0x01]),
+        Some(PathBuf::from("/debug/.build-id/ab/cd01.debug"))
+    );
+}
+
+#[test]
+fn test_symbolize() {
+    #[inline(never)]
+    fn symbolize_me() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    let maps = read_maps(&std::process::id().to_string());
+    let address = symbolize_me as *const () as u64;
+    let mut symbolizer = Symbolizer::new(&[]);
+
+    let symbol = symbolizer.resolve(&maps, address).unwrap();
+    assert!(symbol.function.ends_with("symbolize_me"), "{symbol:?}");
+    assert!(symbol.file.unwrap().ends_with("symbolize.rs"));
+
+    let frame = symbolizer.line(&maps, &format!("#0  {address:#018x}"));
+    assert!(frame.contains("::symbolize_me at "), "{frame}");
+    let frame = symbolizer.line(&maps, &format!("#0  {address:#018x} in ?? () from /tmp/cs"));
+    assert!(frame.contains("::symbolize_me () at "), "{frame}");
+
+    // frames with symbol, and addresses not mapped, are kept as they are
+    for frame in ["#0  0x00007f83ddc5363f __poll", "#1  0x0000000000000010"] {
+        assert_eq!(symbolizer.line(&maps, frame), frame);
+    }
+}
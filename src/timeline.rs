@@ -0,0 +1,195 @@
+// Timeline of repeated samples: which unique stack each thread is in at each sample, so threads
+// moving from one stack to another (`recv`, then a lock, then `write`...) can be followed.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::{filter::StackFilter, format::InputFormat, sample::Sample, utils::format_time};
+
+/// Threads of a process over samples.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    /// Time each sample is taken at.
+    times: Vec<Option<SystemTime>>,
+    /// Unique stacks, numbered by first appearance.
+    stacks: Vec<String>,
+    keys: HashMap<String, usize>,
+    /// Thread label and index of its stack at each sample, None if the thread is not sampled.
+    rows: Vec<(String, Vec<Option<usize>>)>,
+}
+
+impl Timeline {
+    /// from_samples  -  timeline of threads of `samples` passing `filter`.
+    pub fn from_samples(
+        samples: &[Sample],
+        format: InputFormat,
+        filter: &StackFilter,
+    ) -> Result<Timeline, String> {
+        let mut timeline = Timeline::default();
+        let mut rows: HashMap<String, usize> = HashMap::new();
+        for (i, sample) in samples.iter().enumerate() {
+            timeline.times.push(sample.time);
+            for thread in sample.threads(format)? {
+                let Some(thread) = filter.apply(thread) else {
+                    continue;
+                };
+
+                // addresses are dropped, stacks of threads of other processes may be merged later
+                let key = thread.key(true);
+                let stack = match timeline.keys.get(&key) {
+                    Some(n) => *n,
+                    None => {
+                        timeline.stacks.push(thread.stack());
+                        timeline.keys.insert(key, timeline.stacks.len() - 1);
+                        timeline.stacks.len() - 1
+                    }
+                };
+
+                let label = thread.title();
+                let row = *rows.entry(label.clone()).or_insert_with(|| {
+                    timeline.rows.push((label, vec![]));
+                    timeline.rows.len() - 1
+                });
+                let cells = &mut timeline.rows[row].1;
+                cells.resize(i + 1, None);
+                cells[i] = Some(stack);
+            }
+        }
+
+        for (_, cells) in timeline.rows.iter_mut() {
+            cells.resize(samples.len(), None);
+        }
+        Ok(timeline)
+    }
+
+    /// print  -  one row per thread and one column per sample, followed by stacks referred to.
+    // Columns are headed by the time since the first sample, whose time of day is given once.
+    pub fn print(&self) -> String {
+        if self.rows.is_empty() {
+            return "Timeline: no threads".to_owned();
+        }
+
+        let first = self.times.first().copied().flatten();
+        let headers: Vec<String> = self
+            .times
+            .iter()
+            .map(|time| match (first, time) {
+                (Some(first), Some(time)) => {
+                    let elapsed = time.duration_since(first).unwrap_or_default();
+                    format!("+{:.2}s", elapsed.as_secs_f32())
+                }
+                _ => "?".to_owned(),
+            })
+            .collect();
+        let column = headers
+            .iter()
+            .map(|h| h.len())
+            .chain([format!("S{}", self.stacks.len()).len()])
+            .max()
+            .unwrap();
+        let width = self
+            .rows
+            .iter()
+            .map(|(label, _)| label.len())
+            .chain(["Thread".len()])
+            .max()
+            .unwrap();
+
+        let mut output = format!("Timeline of {} samples", self.times.len());
+        if let Some(first) = first {
+            output.push_str(&format!(", from {} UTC", format_time(first)));
+        }
+        output.push_str(&format!(":\n{:<width$}", "Thread"));
+        for header in headers {
+            output.push_str(&format!("  {header:<column$}"));
+        }
+        output.push('\n');
+        for (label, cells) in self.rows.iter() {
+            let mut line = format!("{label:<width$}");
+            for cell in cells {
+                let cell = cell.map_or("-".to_owned(), |n| format!("S{}", n + 1));
+                line.push_str(&format!("  {cell:<column$}"));
+            }
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+
+        for (i, stack) in self.stacks.iter().enumerate() {
+            output.push_str(&format!("\nS{}:\n{stack}", i + 1));
+        }
+        output
+    }
+}
+
+#[test]
+fn test_timeline() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let sample = |secs: f32, output: &str| Sample {
+        pid: Some("37746".to_owned()),
+        output: output.to_owned(),
+        time: Some(UNIX_EPOCH + Duration::from_secs(3600) + Duration::from_secs_f32(secs)),
+        ..Default::default()
+    };
+    let samples = vec![
+        sample(
+            0.0,
+            r#"
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e7 in recv () from /usr/lib64/libc.so.6
+Thread 1 (Thread 0x7f29ce816740 (LWP 37746) "main"):
+#0  0x00007f29ce8db9e7 in __poll () from /usr/lib64/libc.so.6
+"#,
+        ),
+        sample(
+            0.5,
+            r#"
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e8 in __lll_lock_wait () from /usr/lib64/libc.so.6
+Thread 1 (Thread 0x7f29ce816740 (LWP 37746) "main"):
+#0  0x00007f29ce8db9e8 in __poll () from /usr/lib64/libc.so.6
+"#,
+        ),
+        sample(
+            1.0,
+            r#"
+Thread 3 (Thread 0x7f29ce816740 (LWP 37749) "new"):
+#0  0x00007f29ce8db9e7 in recv () from /usr/lib64/libc.so.6
+Thread 2 (Thread 0x7f29ce816740 (LWP 37748) "worker"):
+#0  0x00007f29ce8db9e7 in write () from /usr/lib64/libc.so.6
+"#,
+        ),
+    ];
+
+    let timeline =
+        Timeline::from_samples(&samples, InputFormat::Gdb, &StackFilter::default()).unwrap();
+    assert_eq!(timeline.stacks.len(), 4);
+    assert_eq!(
+        timeline.rows,
+        vec![
+            (
+                "37746:37748 (worker)".to_owned(),
+                vec![Some(0), Some(2), Some(3)]
+            ),
+            (
+                "37746:37746 (main)".to_owned(),
+                vec![Some(1), Some(1), None]
+            ),
+            ("37746:37749 (new)".to_owned(), vec![None, None, Some(0)]),
+        ]
+    );
+
+    let output = timeline.print();
+    assert!(
+        output.starts_with(
+            "Timeline of 3 samples, from 01:00:00.000 UTC:\n\
+             Thread                +0.00s  +0.50s  +1.00s\n\
+             37746:37748 (worker)  S1      S3      S4\n\
+             37746:37746 (main)    S2      S2      -\n\
+             37746:37749 (new)     -       -       S1\n"
+        ),
+        "{output}"
+    );
+    assert!(output.contains("\nS3:\n#0  0x00007f29ce8db9e8 in __lll_lock_wait () from"));
+
+    assert_eq!(Timeline::default().print(), "Timeline: no threads");
+}
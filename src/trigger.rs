@@ -0,0 +1,276 @@
+// Triggered capture: watch processes cheaply through /proc, and capture stacks only once a
+// condition fires, so intermittent hangs and spins are caught without anyone watching.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    process::exit,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    args::Cli,
+    procfs::{read_rss, read_task_stats, TaskStat},
+    utils::format_time,
+};
+
+/// Interval between checks of conditions.
+const POLL_INTERVAL: f32 = 0.5;
+
+/// clock_ticks  -  clock ticks per second of CPU times in `/proc/<pid>/task/<tid>/stat` (USER_HZ).
+// USER_HZ is not 100 on every system, ask for it, falling back to the usual value.
+fn clock_ticks() -> f32 {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 {
+        ticks as f32
+    } else {
+        100.0
+    }
+}
+
+/// Condition firing a capture.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    // CPU usage of process above percentage, may be above 100 for several threads
+    Cpu(f32),
+    // a thread in uninterruptible sleep for more than seconds
+    DState(f32),
+    // RSS grown by bytes since monitoring started
+    RssGrowth(u64),
+    // number of threads changed
+    Threads,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Cpu(percent) => write!(f, "cpu>{percent}"),
+            Trigger::DState(secs) => write!(f, "dstate>{secs}"),
+            Trigger::RssGrowth(bytes) => write!(f, "rss+{bytes}"),
+            Trigger::Threads => write!(f, "threads"),
+        }
+    }
+}
+
+/// parse_size  -  parse size in bytes, with an optional K, M or G suffix.
+fn parse_size(s: &str) -> Option<u64> {
+    let (number, shift) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 10),
+        'M' => (&s[..s.len() - 1], 20),
+        'G' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// parse_trigger  -  parse condition given to --trigger: `cpu>PERCENT`, `dstate>SECONDS`,
+/// `rss+SIZE` or `threads`.
+pub fn parse_trigger(s: &str) -> Result<Trigger, String> {
+    let trigger = if s == "threads" {
+        Some(Trigger::Threads)
+    } else if let Some(percent) = s.strip_prefix("cpu>") {
+        percent.parse().ok().map(Trigger::Cpu)
+    } else if let Some(secs) = s.strip_prefix("dstate>") {
+        secs.parse().ok().map(Trigger::DState)
+    } else if let Some(size) = s.strip_prefix("rss+") {
+        parse_size(size).map(Trigger::RssGrowth)
+    } else {
+        None
+    };
+    trigger.ok_or_else(|| {
+        format!("expecting cpu>PERCENT, dstate>SECONDS, rss+SIZE or threads, got '{s}'")
+    })
+}
+
+/// What is known of a process monitored, from previous checks.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    /// Time and CPU time of all threads at previous check.
+    cpu: Option<(Instant, u64)>,
+    /// RSS and number of threads at first check.
+    rss: Option<u64>,
+    threads: Option<usize>,
+    /// Time threads are first seen in uninterruptible sleep, keyed by tid.
+    dstate: HashMap<String, Instant>,
+}
+
+impl Monitor {
+    /// check  -  account status of a process at `now`, returns why a capture fires, if any.
+    pub fn check(
+        &mut self,
+        triggers: &[Trigger],
+        tasks: &HashMap<String, TaskStat>,
+        rss: Option<u64>,
+        now: Instant,
+    ) -> Option<String> {
+        let cpu: u64 = tasks.values().map(|t| t.utime + t.stime).sum();
+        // threads exiting take their CPU time away
+        let usage = self.cpu.map(|(then, ticks)| {
+            let elapsed = now.duration_since(then).as_secs_f32().max(f32::EPSILON);
+            cpu.saturating_sub(ticks) as f32 / clock_ticks() / elapsed * 100.0
+        });
+        self.cpu = Some((now, cpu));
+        // RSS may not be readable at every check, the baseline is the first value read
+        if self.rss.is_none() {
+            self.rss = rss;
+        }
+        let rss_start = self.rss.unwrap_or(0);
+        let threads_start = *self.threads.get_or_insert(tasks.len());
+        self.dstate
+            .retain(|tid, _| tasks.get(tid).is_some_and(|t| t.state == 'D'));
+        for (tid, task) in tasks {
+            if task.state == 'D' {
+                self.dstate.entry(tid.clone()).or_insert(now);
+            }
+        }
+
+        triggers.iter().find_map(|trigger| match trigger {
+            Trigger::Cpu(limit) => usage
+                .filter(|usage| usage > limit)
+                .map(|usage| format!("CPU usage {usage:.1}% above {limit}%")),
+            Trigger::DState(limit) => self
+                .dstate
+                .iter()
+                .map(|(tid, since)| (tid, now.duration_since(*since)))
+                .filter(|(_, d)| *d > Duration::from_secs_f32(*limit))
+                .max_by_key(|(_, d)| *d)
+                .map(|(tid, d)| {
+                    format!(
+                        "thread {tid} in D state for {:.1}s, more than {limit}s",
+                        d.as_secs_f32()
+                    )
+                }),
+            Trigger::RssGrowth(limit) => rss
+                .filter(|rss| rss.saturating_sub(rss_start) > *limit)
+                .map(|rss| format!("RSS grown by {} bytes, more than {limit}", rss - rss_start)),
+            Trigger::Threads => (tasks.len() != threads_start).then(|| {
+                format!(
+                    "number of threads changed from {threads_start} to {}",
+                    tasks.len()
+                )
+            }),
+        })
+    }
+}
+
+/// wait_trigger  -  monitor processes of `cli` until a condition fires, returns processes to
+/// capture stacks of.
+pub async fn wait_trigger(cli: &Cli) -> Vec<String> {
+    let Some(pids) = cli.pids.clone() else {
+        eprintln!("Needs pid to monitor.");
+        exit(2);
+    };
+
+    let conditions: Vec<String> = cli.triggers.iter().map(|t| t.to_string()).collect();
+    println!(
+        "Waiting for {} on process: {}",
+        conditions.join(" or "),
+        pids.join(", ")
+    );
+    let mut monitors: Vec<Monitor> = pids.iter().map(|_| Monitor::default()).collect();
+    loop {
+        let mut fired = vec![];
+        for (pid, monitor) in pids.iter().zip(monitors.iter_mut()) {
+            let tasks = read_task_stats(pid);
+            if tasks.is_empty() {
+                eprintln!("Process {pid} is gone.");
+                exit(2);
+            }
+            if let Some(reason) =
+                monitor.check(&cli.triggers, &tasks, read_rss(pid), Instant::now())
+            {
+                println!(
+                    "Triggered at {} UTC on process {pid}: {reason}",
+                    format_time(std::time::SystemTime::now())
+                );
+                fired.push(pid.clone());
+            }
+        }
+        if !fired.is_empty() {
+            return fired;
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs_f32(POLL_INTERVAL)).await;
+    }
+}
+
+#[test]
+fn test_parse_trigger() {
+    assert_eq!(parse_trigger("cpu>80"), Ok(Trigger::Cpu(80.0)));
+    assert_eq!(parse_trigger("dstate>2.5"), Ok(Trigger::DState(2.5)));
+    assert_eq!(parse_trigger("rss+64M"), Ok(Trigger::RssGrowth(64 << 20)));
+    assert_eq!(parse_trigger("rss+4096"), Ok(Trigger::RssGrowth(4096)));
+    assert_eq!(parse_trigger("threads"), Ok(Trigger::Threads));
+    assert!(parse_trigger("cpu>lots").is_err());
+    assert!(parse_trigger("rss+12X").is_err());
+    assert!(parse_trigger("mem>1").is_err());
+    assert_eq!(Trigger::Cpu(80.0).to_string(), "cpu>80");
+}
+
+#[test]
+fn test_monitor() {
+    // CPU times given in hundredths of seconds
+    let hz = clock_ticks() as u64;
+    let tasks = |states: &[(&str, char, u64)]| {
+        states
+            .iter()
+            .map(|(tid, state, utime)| {
+                let stat = TaskStat {
+                    state: *state,
+                    utime: *utime * hz / 100,
+                    ..Default::default()
+                };
+                (tid.to_string(), stat)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    let start = Instant::now();
+    let at = |secs: f32| start + Duration::from_secs_f32(secs);
+
+    let triggers = [Trigger::Cpu(150.0), Trigger::DState(2.0)];
+    let mut monitor = Monitor::default();
+    let idle = tasks(&[("1", 'S', 100), ("2", 'S', 0)]);
+    assert_eq!(monitor.check(&triggers, &idle, None, at(0.0)), None);
+    // 1 second of CPU time in 1 second
+    let busy = tasks(&[("1", 'R', 200), ("2", 'S', 0)]);
+    assert_eq!(monitor.check(&triggers, &busy, None, at(1.0)), None);
+    let busy = tasks(&[("1", 'R', 300), ("2", 'R', 100)]);
+    assert_eq!(
+        monitor.check(&triggers, &busy, None, at(2.0)),
+        Some("CPU usage 200.0% above 150%".to_owned())
+    );
+
+    let mut monitor = Monitor::default();
+    let blocked = tasks(&[("1", 'S', 100), ("2", 'D', 0)]);
+    assert_eq!(monitor.check(&triggers, &blocked, None, at(0.0)), None);
+    assert_eq!(monitor.check(&triggers, &blocked, None, at(1.5)), None);
+    // thread left D state meanwhile
+    assert_eq!(monitor.check(&triggers, &idle, None, at(2.0)), None);
+    assert_eq!(monitor.check(&triggers, &blocked, None, at(3.0)), None);
+    assert_eq!(
+        monitor.check(&triggers, &blocked, None, at(5.5)),
+        Some("thread 2 in D state for 2.5s, more than 2s".to_owned())
+    );
+
+    let triggers = [Trigger::RssGrowth(1 << 20), Trigger::Threads];
+    let mut monitor = Monitor::default();
+    // RSS not read yet is not taken as baseline
+    assert_eq!(monitor.check(&triggers, &idle, None, at(0.0)), None);
+    assert_eq!(
+        monitor.check(&triggers, &idle, Some(4 << 20), at(0.0)),
+        None
+    );
+    assert_eq!(
+        monitor.check(&triggers, &idle, Some(5 << 20), at(1.0)),
+        None
+    );
+    assert_eq!(
+        monitor.check(&triggers, &idle, Some(6 << 20), at(2.0)),
+        Some("RSS grown by 2097152 bytes, more than 1048576".to_owned())
+    );
+    let more = tasks(&[("1", 'S', 100), ("2", 'S', 0), ("3", 'S', 0)]);
+    assert_eq!(
+        monitor.check(&triggers, &more, Some(4 << 20), at(3.0)),
+        Some("number of threads changed from 2 to 3".to_owned())
+    );
+}
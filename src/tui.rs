@@ -0,0 +1,465 @@
+// Full-screen browser of unique stacks.
+// Groups are listed on the left, with their number of threads, the stack of the selected group is
+// shown on the right. The terminal is opened through /dev/tty, so stacks can be read from stdin.
+
+use regex::Regex;
+use std::io::{self, Write};
+use termion::{
+    clear, color, cursor, event::Key, input::TermRead, raw::IntoRawMode,
+    screen::IntoAlternateScreen, style,
+};
+
+use crate::{
+    classify::{stack_class, ThreadClass},
+    stack::function_name,
+    uniquify::{sort_groups, Notes},
+};
+
+/// Number of frames kept at top and bottom of folded stacks.
+const FOLD_TOP: usize = 6;
+const FOLD_BOTTOM: usize = 2;
+
+/// fold_frames  -  lines of `stack`, with frames in the middle folded into one line.
+pub fn fold_frames(stack: &str) -> Vec<String> {
+    let lines: Vec<&str> = stack.lines().collect();
+    if lines.len() <= FOLD_TOP + FOLD_BOTTOM + 1 {
+        return lines.into_iter().map(|s| s.to_owned()).collect();
+    }
+
+    let folded = lines.len() - FOLD_TOP - FOLD_BOTTOM;
+    let mut result: Vec<String> = lines[..FOLD_TOP].iter().map(|s| s.to_string()).collect();
+    result.push(format!("    ... {folded} frames folded ..."));
+    result.extend(
+        lines[lines.len() - FOLD_BOTTOM..]
+            .iter()
+            .map(|s| s.to_string()),
+    );
+    result
+}
+
+/// What keys typed are used for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Browse,
+    // regex filtering groups, being typed
+    Filter(String),
+    // file to export groups to, being typed, and whether all listed groups are exported
+    Export(String, bool),
+}
+
+/// State of the browser, drawn after every key.
+pub struct Browser {
+    groups: Vec<(String, Vec<String>)>,
+    /// Known issue or fingerprint of stacks, shown below them.
+    notes: Notes,
+    suspicious: Vec<bool>,
+    /// Indices of groups matching filter.
+    listed: Vec<usize>,
+    /// Position of selected group in `listed`.
+    selected: usize,
+    /// First line of stack shown.
+    scroll: usize,
+    folded: bool,
+    filter: Option<Regex>,
+    pub input: Input,
+    /// Message shown in status line, until next key.
+    pub message: String,
+}
+
+impl Browser {
+    pub fn new(mut groups: Vec<(String, Vec<String>)>, notes: Notes) -> Browser {
+        sort_groups(&mut groups);
+        let suspicious = groups
+            .iter()
+            .map(|(stack, _)| {
+                matches!(
+                    stack_class(stack),
+                    Some(ThreadClass::Crashed | ThreadClass::Lock)
+                )
+            })
+            .collect();
+        Browser {
+            listed: (0..groups.len()).collect(),
+            groups,
+            notes,
+            suspicious,
+            selected: 0,
+            scroll: 0,
+            folded: false,
+            filter: None,
+            input: Input::Browse,
+            message: String::new(),
+        }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.listed.get(self.selected).copied()
+    }
+
+    /// stack_lines  -  lines of selected stack, folded if asked to.
+    pub fn stack_lines(&self) -> Vec<String> {
+        let Some(i) = self.selected() else {
+            return vec![];
+        };
+        let (stack, labels) = &self.groups[i];
+        let mut lines = vec![format!(
+            "Number of thread: {} -- {}",
+            labels.len(),
+            labels.join(", ")
+        )];
+        if self.folded {
+            lines.extend(fold_frames(stack));
+        } else {
+            lines.extend(stack.lines().map(|s| s.to_owned()));
+        }
+        lines.extend(self.notes.get(stack).cloned());
+        lines
+    }
+
+    fn select(&mut self, position: usize) {
+        self.selected = position.min(self.listed.len().saturating_sub(1));
+        self.scroll = 0;
+    }
+
+    /// set_filter  -  list groups whose stack or threads match `pattern`, all if it is empty.
+    pub fn set_filter(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            self.filter = None;
+        } else {
+            match Regex::new(pattern) {
+                Ok(re) => self.filter = Some(re),
+                Err(err) => {
+                    self.message = format!("Invalid regex: {err}");
+                    return;
+                }
+            }
+        }
+
+        let current = self.selected();
+        self.listed = (0..self.groups.len())
+            .filter(|i| {
+                let (stack, labels) = &self.groups[*i];
+                self.filter
+                    .as_ref()
+                    .is_none_or(|re| re.is_match(stack) || labels.iter().any(|l| re.is_match(l)))
+            })
+            .collect();
+        let position = current
+            .and_then(|i| self.listed.iter().position(|j| *j == i))
+            .unwrap_or(0);
+        self.select(position);
+        self.message = format!(
+            "{} of {} groups listed",
+            self.listed.len(),
+            self.groups.len()
+        );
+    }
+
+    /// next_suspicious  -  select next listed group that crashed or waits for a lock.
+    pub fn next_suspicious(&mut self) {
+        let count = self.listed.len();
+        match (1..=count)
+            .map(|n| (self.selected + n) % count)
+            .find(|p| self.suspicious[self.listed[*p]])
+        {
+            Some(position) => self.select(position),
+            None => self.message = "No suspicious group listed".to_owned(),
+        }
+    }
+
+    /// export  -  write selected group, or all listed groups, to file `path`.
+    pub fn export(&mut self, path: &str, all: bool) {
+        let indices: Vec<usize> = if all {
+            self.listed.clone()
+        } else {
+            self.selected().into_iter().collect()
+        };
+        let content: Vec<String> = indices
+            .iter()
+            .map(|i| {
+                let (stack, labels) = &self.groups[*i];
+                let mut group = format!(
+                    "Number of thread: {} -- {}:\n{}",
+                    labels.len(),
+                    labels.join(", "),
+                    stack
+                );
+                if let Some(note) = self.notes.get(stack) {
+                    group.push_str(&format!("{note}\n"));
+                }
+                group
+            })
+            .collect();
+        self.message = match std::fs::write(path, content.join("\n")) {
+            Ok(_) => format!("{} group(s) exported to {path}", indices.len()),
+            Err(err) => format!("Failed to export to {path}: {err}"),
+        };
+    }
+
+    /// handle  -  update state for `key`, returns false when browser is closed.
+    // `page` is number of lines a page up or down moves.
+    pub fn handle(&mut self, key: Key, page: usize) -> bool {
+        self.message.clear();
+        match std::mem::replace(&mut self.input, Input::Browse) {
+            Input::Filter(mut pattern) => match key {
+                Key::Char('\n') => self.set_filter(&pattern),
+                Key::Esc => {}
+                Key::Backspace => {
+                    pattern.pop();
+                    self.input = Input::Filter(pattern);
+                }
+                Key::Char(c) => {
+                    pattern.push(c);
+                    self.input = Input::Filter(pattern);
+                }
+                _ => self.input = Input::Filter(pattern),
+            },
+            Input::Export(mut path, all) => match key {
+                Key::Char('\n') => self.export(&path, all),
+                Key::Esc => {}
+                Key::Backspace => {
+                    path.pop();
+                    self.input = Input::Export(path, all);
+                }
+                Key::Char(c) => {
+                    path.push(c);
+                    self.input = Input::Export(path, all);
+                }
+                _ => self.input = Input::Export(path, all),
+            },
+            Input::Browse => match key {
+                Key::Char('q') | Key::Esc | Key::Ctrl('c') => return false,
+                Key::Up | Key::Char('k') => self.select(self.selected.saturating_sub(1)),
+                Key::Down | Key::Char('j') => self.select(self.selected + 1),
+                Key::Home | Key::Char('g') => self.select(0),
+                Key::End | Key::Char('G') => self.select(usize::MAX),
+                Key::PageDown | Key::Char(' ') => {
+                    let last = self.stack_lines().len().saturating_sub(1);
+                    self.scroll = (self.scroll + page).min(last);
+                }
+                Key::PageUp | Key::Char('b') => self.scroll = self.scroll.saturating_sub(page),
+                Key::Char('f') => {
+                    self.folded = !self.folded;
+                    self.scroll = 0;
+                }
+                Key::Char('s') | Key::Char('n') => self.next_suspicious(),
+                Key::Char('/') => {
+                    let pattern = self.filter.as_ref().map(|re| re.to_string());
+                    self.input = Input::Filter(pattern.unwrap_or_default());
+                }
+                Key::Char(c @ ('e' | 'E')) => {
+                    let name = match (c, self.selected()) {
+                        ('e', Some(i)) => format!("stack-{}.txt", i + 1),
+                        _ => "stacks.txt".to_owned(),
+                    };
+                    self.input = Input::Export(name, c == 'E');
+                }
+                _ => {}
+            },
+        }
+        true
+    }
+
+    /// draw  -  draw the browser on a `width` x `height` screen.
+    pub fn draw<W: Write>(&self, out: &mut W, width: usize, height: usize) -> io::Result<()> {
+        let truncate = |s: &str, n: usize| s.chars().take(n).collect::<String>();
+        let left = (width / 3).clamp(20, 48).min(width.saturating_sub(2));
+        let right = width.saturating_sub(left + 1);
+        let rows = height.saturating_sub(1);
+
+        write!(out, "{}{}", clear::All, cursor::Goto(1, 1))?;
+
+        // keep selected group in view
+        let first = self.selected.saturating_sub(rows.saturating_sub(1));
+        for (row, position) in (first..self.listed.len()).take(rows).enumerate() {
+            let i = self.listed[position];
+            let (stack, labels) = &self.groups[i];
+            let top = stack.lines().next().map(function_name).unwrap_or("");
+            let text = truncate(&format!("{:>5} {top}", labels.len()), left);
+            write!(out, "{}", cursor::Goto(1, row as u16 + 1))?;
+            if self.suspicious[i] {
+                write!(out, "{}", color::Fg(color::Red))?;
+            }
+            if position == self.selected {
+                write!(out, "{}{text:<left$}{}", style::Invert, style::Reset)?;
+            } else {
+                write!(out, "{text}")?;
+            }
+            write!(out, "{}", color::Fg(color::Reset))?;
+        }
+
+        for row in 0..rows {
+            write!(out, "{}|", cursor::Goto(left as u16 + 1, row as u16 + 1))?;
+        }
+        for (row, line) in self
+            .stack_lines()
+            .iter()
+            .skip(self.scroll)
+            .take(rows)
+            .enumerate()
+        {
+            write!(
+                out,
+                "{}{}",
+                cursor::Goto(left as u16 + 2, row as u16 + 1),
+                truncate(line, right)
+            )?;
+        }
+
+        let status = match &self.input {
+            Input::Filter(pattern) => format!("Filter (regex): {pattern}"),
+            Input::Export(path, all) => {
+                let what = if *all { "listed groups" } else { "selected group" };
+                format!("Export {what} to: {path}")
+            }
+            Input::Browse if !self.message.is_empty() => self.message.clone(),
+            Input::Browse => format!(
+                "[{}/{}] j/k: select  PgUp/PgDn: scroll  f: fold  /: filter  s: suspicious  e/E: export  q: quit",
+                self.selected + 1,
+                self.listed.len()
+            ),
+        };
+        write!(
+            out,
+            "{}{}{}{}",
+            cursor::Goto(1, height as u16),
+            style::Bold,
+            truncate(&status, width),
+            style::Reset
+        )?;
+        out.flush()
+    }
+}
+
+/// browse  -  browse `groups` until user quits.
+pub fn browse(groups: Vec<(String, Vec<String>)>, notes: Notes) -> Result<(), String> {
+    if groups.is_empty() {
+        return Err("no stacks to browse".to_owned());
+    }
+
+    let run = || -> io::Result<()> {
+        let input = termion::get_tty()?;
+        let mut screen = termion::get_tty()?
+            .into_raw_mode()?
+            .into_alternate_screen()?;
+        write!(screen, "{}", cursor::Hide)?;
+
+        let mut browser = Browser::new(groups, notes);
+        let size = || termion::terminal_size().map_or((80, 24), |(w, h)| (w as usize, h as usize));
+        let (width, height) = size();
+        browser.draw(&mut screen, width, height)?;
+        for key in input.keys() {
+            let (width, height) = size();
+            if !browser.handle(key?, height.saturating_sub(2)) {
+                break;
+            }
+            browser.draw(&mut screen, width, height)?;
+        }
+
+        write!(screen, "{}", cursor::Show)?;
+        screen.flush()
+    };
+    run().map_err(|err| format!("terminal error: {err}"))
+}
+
+#[test]
+fn test_fold_frames() {
+    let stack: String = (0..12).map(|n| format!("#{n}  0x1 f{n}\n")).collect();
+    let folded = fold_frames(&stack);
+    assert_eq!(folded.len(), FOLD_TOP + 1 + FOLD_BOTTOM);
+    assert_eq!(folded[FOLD_TOP], "    ... 4 frames folded ...");
+    assert_eq!(folded.last().unwrap(), "#11  0x1 f11");
+
+    let stack: String = (0..9).map(|n| format!("#{n}  0x1 f{n}\n")).collect();
+    assert_eq!(fold_frames(&stack).len(), 9);
+}
+
+#[test]
+fn test_browser() {
+    let groups = vec![
+        (
+            "#0  0x1 __poll\n#1  0x2 main\n".to_owned(),
+            vec!["101".to_owned()],
+        ),
+        (
+            "#0  0x1 epoll_wait\n#1  0x2 worker\n".to_owned(),
+            vec!["102".to_owned(), "103".to_owned(), "104".to_owned()],
+        ),
+        (
+            "#0  0x1 __lll_lock_wait\n#1  0x2 pthread_mutex_lock\n#2  0x3 flush\n".to_owned(),
+            vec!["105".to_owned(), "106".to_owned()],
+        ),
+    ];
+    let mut browser = Browser::new(groups, Notes::new());
+
+    // most common stacks go first
+    assert_eq!(
+        browser.stack_lines()[0],
+        "Number of thread: 3 -- 102, 103, 104"
+    );
+    assert!(browser.handle(Key::Down, 10));
+    assert_eq!(browser.stack_lines()[1], "#0  0x1 __lll_lock_wait");
+    assert!(browser.handle(Key::Down, 10));
+    assert!(browser.handle(Key::Down, 10));
+    assert_eq!(browser.selected(), Some(2));
+
+    // suspicious group is found wrapping around
+    browser.handle(Key::Char('s'), 10);
+    assert_eq!(browser.selected(), Some(1));
+
+    for key in "/main\n".chars() {
+        browser.handle(Key::Char(key), 10);
+    }
+    assert_eq!(browser.selected(), Some(2));
+    assert_eq!(browser.message, "1 of 3 groups listed");
+    browser.handle(Key::Char('s'), 10);
+    assert_eq!(browser.message, "No suspicious group listed");
+
+    browser.handle(Key::Char('/'), 10);
+    assert_eq!(browser.input, Input::Filter("main".to_owned()));
+    for key in [
+        Key::Backspace,
+        Key::Backspace,
+        Key::Backspace,
+        Key::Backspace,
+    ] {
+        browser.handle(key, 10);
+    }
+    browser.handle(Key::Char('\n'), 10);
+    assert_eq!(browser.message, "3 of 3 groups listed");
+    // selection is kept when filter changes
+    assert_eq!(browser.selected(), Some(2));
+
+    browser.handle(Key::Char('/'), 10);
+    browser.handle(Key::Char('('), 10);
+    browser.handle(Key::Char('\n'), 10);
+    assert!(browser.message.starts_with("Invalid regex"));
+
+    let dir = std::env::temp_dir().join(format!("cs-tui-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("all.txt");
+    browser.handle(Key::Char('E'), 10);
+    assert_eq!(browser.input, Input::Export("stacks.txt".to_owned(), true));
+    browser.input = Input::Export(path.to_string_lossy().to_string(), true);
+    browser.handle(Key::Char('\n'), 10);
+    assert!(browser.message.starts_with("3 group(s) exported"));
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with("Number of thread: 3 -- 102, 103, 104:\n#0  0x1 epoll_wait\n"));
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let mut screen = vec![];
+    browser.draw(&mut screen, 80, 10).unwrap();
+    let screen = String::from_utf8(screen).unwrap();
+    assert!(screen.contains("    3 epoll_wait"));
+    assert!(screen.contains("3 group(s) exported"));
+
+    // message lasts until next key
+    browser.handle(Key::Char('x'), 10);
+    let mut screen = vec![];
+    browser.draw(&mut screen, 80, 10).unwrap();
+    assert!(String::from_utf8(screen)
+        .unwrap()
+        .contains("[3/3] j/k: select"));
+
+    assert!(!browser.handle(Key::Char('q'), 10));
+}
@@ -4,61 +4,269 @@ use pager::Pager;
 use regex::Regex;
 use std::{
     collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Write},
     process::exit,
     sync::{Arc, Mutex},
 };
-use tokio::fs;
 
-use crate::{args::Cli, utils::ensure_file_exists};
+use crate::{
+    args::Cli,
+    classify::{classify, stack_class, ClassSummary},
+    demangle::Demangle,
+    filter::StackFilter,
+    format::{FormatDetector, InputFormat},
+    hot::HotFrames,
+    locks::{lock_wait, print_deadlocks},
+    patterns,
+    stack::{label_flags, Thread},
+    symbolize::Symbolizer,
+    tui::browse,
+    utils::{ensure_file_exists, sampling_prefix},
+};
+
+/// Known issue or fingerprint shown below stacks, keyed by stack.
+pub type Notes = HashMap<String, String>;
+
+/// Threads grouped by their call stacks.
+// Groups are built incrementally as threads are parsed, and only the first thread of each group
+// keeps its frames, so memory usage is bounded by number of unique stacks rather than size of input.
+#[derive(Default)]
+pub struct StackGroups {
+    // (first thread of group, other threads of group without frames)
+    groups: Vec<(Thread, Vec<Thread>)>,
+    /// Known issue or fingerprint of each group, if signatures are matched.
+    notes: Vec<Option<String>>,
+    index: HashMap<String, usize>,
+    filter: StackFilter,
+}
+
+impl StackGroups {
+    pub fn new(filter: StackFilter) -> StackGroups {
+        StackGroups {
+            filter,
+            ..Default::default()
+        }
+    }
+
+    /// from_threads  -  group `threads`, keeping those passing `filter`.
+    pub fn from_threads(threads: &[Thread], filter: &StackFilter) -> StackGroups {
+        let mut groups = StackGroups::new(filter.clone());
+        for thread in threads {
+            groups.add(thread.clone());
+        }
+        groups
+    }
+
+    // Groups are noted from frames as read, demangled, whatever frames are filtered or shown.
+    pub fn add(&mut self, thread: Thread) {
+        let read = self.filter.signatures.is_some().then(|| thread.stack());
+        let Some(thread) = self.filter.apply(thread) else {
+            return;
+        };
+
+        let stack = thread.stack();
+        match self.index.get(&stack) {
+            Some(i) => self.groups[*i].1.push(Thread {
+                frames: vec![],
+                ..thread
+            }),
+            None => {
+                let note = self
+                    .filter
+                    .signatures
+                    .as_ref()
+                    .zip(read)
+                    .and_then(|(signatures, read)| signatures.note(&Demangle::Full.stack(read)));
+                self.index.insert(stack, self.groups.len());
+                self.groups.push((thread, vec![]));
+                self.notes.push(note);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: StackGroups) {
+        let notes = other.notes.into_iter().chain(std::iter::repeat(None));
+        for ((first, rest), note) in other.groups.into_iter().zip(notes) {
+            let stack = first.stack();
+            match self.index.get(&stack) {
+                Some(i) => {
+                    let members = &mut self.groups[*i].1;
+                    members.push(Thread {
+                        frames: vec![],
+                        ..first
+                    });
+                    members.extend(rest);
+                }
+                None => {
+                    self.index.insert(stack, self.groups.len());
+                    self.groups.push((first, rest));
+                    self.notes.push(note);
+                }
+            }
+        }
+    }
+
+    /// classes  -  number of threads of each class, per process.
+    // Classes told by stacks are computed once per group, only states differ between its threads.
+    pub fn classes(&self) -> ClassSummary {
+        let threads = || {
+            self.groups
+                .iter()
+                .flat_map(|(first, rest)| std::iter::once(first).chain(rest.iter()))
+        };
+        let (with_source, _) = label_flags(threads());
+
+        let mut summary = ClassSummary::default();
+        for (first, rest) in self.groups.iter() {
+            let class = stack_class(&first.stack());
+            for thread in std::iter::once(first).chain(rest.iter()) {
+                let mut parts = vec![];
+                if with_source || thread.pid.is_none() {
+                    parts.push(thread.source.as_deref().unwrap_or("-"));
+                }
+                parts.extend(thread.pid.as_deref());
+                let process = if parts.is_empty() {
+                    "-".to_owned()
+                } else {
+                    parts.join(":")
+                };
+                summary.add(&process, classify(class, thread.state));
+            }
+        }
+        summary
+    }
+
+    /// into_thread_groups  -  convert into (stack, threads) pairs, for threads of a single process.
+    pub fn into_thread_groups(self) -> Vec<(String, Vec<Thread>)> {
+        self.groups
+            .into_iter()
+            .map(|(first, rest)| {
+                let stack = first.stack();
+                (stack, std::iter::once(first).chain(rest).collect())
+            })
+            .collect()
+    }
 
-fn sort_and_print_stack(cache: HashMap<String, String>) -> Result<String, String> {
-    let mut ordered_cache: HashMap<usize, Vec<(&String, &String)>> = HashMap::new();
-    for (key, val) in cache.iter() {
-        let count = val.chars().filter(|&c| c == ',').count() + 1;
-        let mut v = match ordered_cache.get(&count) {
-            Some(v) => v.clone(),
-            None => vec![],
+    /// into_groups  -  convert into (stack, labels of threads) pairs.
+    pub fn into_groups(self) -> Vec<(String, Vec<String>)> {
+        self.into_noted_groups().0
+    }
+
+    /// into_noted_groups  -  convert into (stack, labels of threads) pairs, and notes of stacks.
+    // Groups are returned in the order stacks are first seen. When threads come from several
+    // processes, stacks are compared without addresses, and the stack of the first thread is shown
+    // for each group, with its note. Notes are kept apart from stacks, so that they are never taken
+    // for frames.
+    pub fn into_noted_groups(self) -> (Vec<(String, Vec<String>)>, Notes) {
+        let threads = || {
+            self.groups
+                .iter()
+                .flat_map(|(first, rest)| std::iter::once(first).chain(rest.iter()))
         };
+        let (with_source, with_pid) = label_flags(threads());
+
+        let mut groups: Vec<(String, Vec<String>)> = vec![];
+        let mut notes = vec![];
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for ((first, rest), note) in self.groups.iter().zip(self.notes.iter()) {
+            let key = first.key(with_pid);
+            let labels = std::iter::once(first)
+                .chain(rest.iter())
+                .map(|t| t.label(with_source, with_pid));
+            match index.get(&key) {
+                Some(i) => groups[*i].1.extend(labels),
+                None => {
+                    index.insert(key, groups.len());
+                    groups.push((first.stack(), labels.collect()));
+                    notes.push(note.clone());
+                }
+            }
+        }
+
+        if let Some(source) = &self.filter.source {
+            source.annotate_groups(&mut groups);
+        }
+        let notes = groups
+            .iter()
+            .zip(notes)
+            .filter_map(|((stack, _), note)| Some((stack.clone(), note?)))
+            .collect();
+        (groups, notes)
+    }
+}
 
-        v.push((val, key));
-        ordered_cache.insert(count, v);
+/// group_threads  -  group threads having identical call stacks.
+pub fn group_threads(threads: &[Thread], filter: &StackFilter) -> Vec<(String, Vec<String>)> {
+    StackGroups::from_threads(threads, filter).into_groups()
+}
+
+/// print_unique  -  print classes of threads per process, then unique stacks of `groups`.
+pub fn print_unique(groups: StackGroups) -> Result<String, String> {
+    let summary = groups.classes().print();
+    let (mut groups, notes) = groups.into_noted_groups();
+    sort_groups(&mut groups);
+    let stacks = print_stacks(&groups, false, &notes);
+    if summary.is_empty() {
+        Ok(stacks)
+    } else {
+        Ok(format!("{summary}\n{stacks}"))
     }
+}
 
-    let keywords = [
-        "__assert_fail",
-        "fatal.*signals",
-        "raise",
-        "segfault",
-        "segment fault",
-        "segmentfault",
-        "signal handler called",
-    ];
+/// sort_groups  -  sort groups of threads, most common stacks go first.
+pub fn sort_groups<T>(groups: &mut [(String, Vec<T>)]) {
+    groups.sort_by(|(s1, t1), (s2, t2)| t2.len().cmp(&t1.len()).then(s1.cmp(s2)));
+}
+
+pub fn sort_and_print_stack(mut groups: Vec<(String, Vec<String>)>) -> Result<String, String> {
+    sort_groups(&mut groups);
+    Ok(print_stacks(&groups, false, &Notes::new()))
+}
 
-    let pattern = format!(r#"(?i)(?P<sus>.*({}).*)"#, keywords.join("|"));
-    let r_match_suspicious = Regex::new(&pattern).unwrap();
+/// print_stacks  -  print sorted `groups`, numbered from 1 if `numbered`, with `notes` of stacks.
+pub fn print_stacks(groups: &[(String, Vec<String>)], numbered: bool, notes: &Notes) -> String {
+    let r_match_suspicious = patterns::suspicious();
     let mut suspicious: Vec<String> = vec![];
 
     let mut outputs = vec![];
-    let pairs: Vec<_> = ordered_cache.iter().collect();
-    for (key, val) in pairs.iter().rev() {
-        for (pids, stack) in *val {
-            if r_match_suspicious.is_match(stack) {
-                suspicious.push((*pids).clone());
-                let stack = r_match_suspicious
-                    .replace_all(stack, |captures: &regex::Captures| {
-                        let matched_text = captures.name("sus").unwrap().as_str();
-                        format!(
-                            "{}{}",
-                            matched_text.blue(),
-                            "                           <---- HERE ".red().bold()
-                        )
-                    })
-                    .to_string();
-                outputs.push(format!("Number of thread: {key} -- {pids}:\n{stack}"));
-            } else {
-                outputs.push(format!("Number of thread: {key} -- {pids}:\n{stack}"));
+    for (i, (stack, tids)) in groups.iter().enumerate() {
+        let number = if numbered {
+            format!("[{}] ", i + 1)
+        } else {
+            "".to_owned()
+        };
+        let key = tids.len();
+        let pids = tids.join(", ");
+        // threads waiting for locks are suspicious too, along with the lock function
+        match lock_wait(stack) {
+            Some(func) => suspicious.push(format!("{pids} ({func})")),
+            None if r_match_suspicious.is_match(stack) => suspicious.push(pids.clone()),
+            None => {}
+        }
+        let mut output = if r_match_suspicious.is_match(stack) {
+            let stack = r_match_suspicious
+                .replace_all(stack, |captures: &regex::Captures| {
+                    let matched_text = captures.name("sus").unwrap().as_str();
+                    format!(
+                        "{}{}",
+                        matched_text.blue(),
+                        "                           <---- HERE ".red().bold()
+                    )
+                })
+                .to_string();
+            format!("{number}Number of thread: {key} -- {pids}:\n{stack}")
+        } else {
+            format!("{number}Number of thread: {key} -- {pids}:\n{stack}")
+        };
+        if let Some(note) = notes.get(stack) {
+            if !output.ends_with('\n') {
+                output.push('\n');
             }
+            output.push_str(note);
+            output.push('\n');
         }
+        outputs.push(output);
     }
 
     if !suspicious.is_empty() {
@@ -68,195 +276,617 @@ fn sort_and_print_stack(cache: HashMap<String, String>) -> Result<String, String
         ));
     }
 
-    Ok(outputs.join("\n"))
+    outputs.join("\n")
 }
 
-pub fn simplify_stack(input: String) -> String {
-    let re = regex::Regex::new(r"\s+in\s+(?P<func>.*?)\s+\(.*?\)\s+(at|from)\s+.*").unwrap();
-    re.replace_all(&input, |captures: &regex::Captures| {
-        let matched_text = captures.name("func").unwrap().as_str();
-        format!(" {}", matched_text)
-    })
-    .to_string()
+/// What is kept of frames when simplifying stacks, besides function name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Simplify {
+    // function name only
+    #[default]
+    Function,
+    // source location too, with full path, as needed to show source
+    Location,
+    // source location, shortened to basename or relative to one of these roots, without address
+    Short(Vec<String>),
 }
 
-pub fn uniquify_eustack(input: &str) -> Result<String, String> {
-    let r_match_pid = Regex::new(r#"PID\s+(?P<pid>\d+)\s+-\s+process"#).unwrap();
-    if r_match_pid.captures(input).is_none() {
-        return Err("not generated by eu-stack".to_owned());
-    };
+impl Simplify {
+    pub fn from_cli(cli: &Cli) -> Simplify {
+        if cli.source.is_some_and(|n| n > 0) {
+            Simplify::Location
+        } else if cli.location_mode {
+            Simplify::Short(cli.source_roots.clone())
+        } else {
+            Simplify::Function
+        }
+    }
+}
 
-    let r_match_tid = Regex::new(r#"TID\s+(?P<tid>\d+):"#).unwrap();
-    let r_match_empty = Regex::new(r#"^$"#).unwrap();
-    let r_match_entry = Regex::new(r#"^#\d+\s+0x.*?$"#).unwrap();
+/// simplify_frame  -  drop arguments and location of a gdb frame, keeping function name only.
+// Does the same as replacing `\s+in\s+(?P<func>.*?)\s+\(.*?\)\s+(at|from)\s+.*` with " $func", by
+// hand, as this runs for every line of input. Source location (`at file:line`) is kept when
+// `keep_location` is set.
+fn simplify_frame(s: &str, keep_location: bool) -> String {
+    let bytes = s.as_bytes();
+    let is_space = |i: usize| bytes.get(i).is_some_and(|c| c.is_ascii_whitespace());
+    let skip_spaces = |mut i: usize| {
+        while is_space(i) {
+            i += 1;
+        }
+        i
+    };
 
-    let mut cache: HashMap<String, String> = HashMap::new();
-    let mut tid = "";
-    let mut stack = "".to_owned();
+    // `)\s+(at|from)\s+` somewhere at or after `from`, returns the word and where text after it starts
+    let find_location = |from: usize| {
+        s[from..].match_indices(')').find_map(|(i, _)| {
+            let i = from + i + 1;
+            let j = skip_spaces(i);
+            if j == i {
+                return None;
+            }
+            ["at", "from"].iter().find_map(|w| {
+                let k = skip_spaces(j + w.len());
+                (s[j..].starts_with(w) && k > j + w.len()).then_some((*w, k))
+            })
+        })
+    };
 
-    for s in input.split('\n') {
-        if r_match_pid.captures(s).is_some() {
+    let mut from = 0;
+    while let Some(i) = s[from..].find("in") {
+        let start = from + i;
+        from = start + 2;
+        if start == 0 || !is_space(start - 1) || !is_space(start + 2) {
             continue;
-        } else if r_match_empty.is_match(s) {
-        } else if let Some(m) = r_match_tid.captures(s) {
-            // start of new stack
-            if !tid.is_empty() {
-                let tids = match cache.get(&stack) {
-                    Some(existing) => format!("{}, {}", existing, tid.to_owned()),
-                    None => tid.to_owned(),
-                };
-                cache.insert(stack.to_string(), tids);
+        }
+
+        // function ends at the first `\s+\(`
+        let func_start = skip_spaces(start + 2);
+        // function name is empty if there are spaces to share between `in` and `(`
+        let lowest = if func_start > start + 3 {
+            start + 3
+        } else {
+            func_start
+        };
+        let paren = s[func_start..].match_indices('(').find_map(|(p, _)| {
+            let p = func_start + p;
+            let mut q = p;
+            while q > lowest && is_space(q - 1) {
+                q -= 1;
             }
+            (q < p).then_some((q, p))
+        });
+
+        let Some((func_end, paren)) = paren else {
+            continue;
+        };
+        let Some((word, location)) = find_location(paren) else {
+            continue;
+        };
 
-            tid = m.name("tid").unwrap().as_str();
-            stack = "".to_string();
-        } else if r_match_entry.is_match(s) {
-            stack = stack + s + "\n";
+        let mut ws_start = start;
+        while ws_start > 0 && is_space(ws_start - 1) {
+            ws_start -= 1;
         }
+        let func = &s[func_start.min(func_end)..func_end];
+        if keep_location && word == "at" {
+            return format!("{} {} at {}", &s[..ws_start], func, &s[location..]);
+        }
+        return format!("{} {}", &s[..ws_start], func);
     }
 
-    // now handle last tid
-    let tids = match cache.get(&stack) {
-        Some(existing) => format!("{}, {}", existing, tid.to_owned()),
-        None => tid.to_owned(),
+    s.to_owned()
+}
+
+/// shorten_frame  -  drop address and arguments of frame `s`, and shorten path of its source.
+// Frames without function name keep their address, it is all we know of them.
+fn shorten_frame(s: &str, roots: &[String]) -> String {
+    let Some(m) = patterns::short_frame().captures(s) else {
+        return s.to_owned();
     };
-    cache.insert(stack.to_string(), tids);
+    let level = m.name("level").unwrap().as_str();
+    let func = m.name("func").unwrap().as_str();
+    if func.is_empty() {
+        return s.to_owned();
+    }
 
-    sort_and_print_stack(cache)
+    let Some(file) = m.name("file").map(|m| m.as_str()) else {
+        return format!("{level:<4}{func}");
+    };
+    let file = roots
+        .iter()
+        .find_map(|root| {
+            file.strip_prefix(root.trim_end_matches('/'))
+                .and_then(|rest| rest.strip_prefix('/'))
+        })
+        .unwrap_or_else(|| file.rsplit('/').next().unwrap_or(file));
+    match m.name("line") {
+        Some(line) => format!("{level:<4}{func} at {file}:{}", line.as_str()),
+        None => format!("{level:<4}{func} at {file}"),
+    }
 }
 
-const RE_MATCH_GDB_TID: &str = r#"Thread\s+(?P<tid>\d+)\s+.*\(LWP\s+(?P<lwp>\d+).*\):"#;
+/// simplify_line  -  simplify frame `s` as asked by `simplify`.
+pub fn simplify_line(s: &str, simplify: &Simplify) -> String {
+    match simplify {
+        Simplify::Function => simplify_frame(s, false),
+        Simplify::Location => simplify_frame(s, true),
+        Simplify::Short(roots) => shorten_frame(&simplify_frame(s, true), roots),
+    }
+}
+
+pub fn simplify_stack(input: String, simplify: &Simplify) -> String {
+    input
+        .split('\n')
+        .map(|s| simplify_line(s, simplify))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// combine_threads  -  group threads of all processes together, and save result into `outputs`.
+pub fn combine_threads(
+    cli: &Cli,
+    filter: &StackFilter,
+    threads: &[Thread],
+    outputs: &Arc<Mutex<Vec<String>>>,
+) {
+    if threads.is_empty() {
+        return;
+    }
+
+    if cli.tui {
+        let (groups, notes) = StackGroups::from_threads(threads, filter).into_noted_groups();
+        if let Err(err) = browse(groups, notes) {
+            eprintln!("Failed to browse stacks: {err}");
+            exit(2);
+        }
+        exit(0);
+    }
 
-pub fn uniquify_gdb(input: &str) -> Result<String, String> {
-    let r_match_tid = Regex::new(RE_MATCH_GDB_TID).unwrap();
-    if r_match_tid.captures(input).is_none() {
-        return Err(format!("not generated by gdb:\n{}", input));
+    let prefix = sampling_prefix(cli.interval, cli.count);
+    match print_unique(StackGroups::from_threads(threads, filter)) {
+        Ok(o) => {
+            let mut output = format!("{}\n{}{}", prefix, o, print_deadlocks(threads));
+            if let Some(limit) = cli.hot {
+                let hot = HotFrames::from_threads(threads, filter);
+                output.push_str(&format!("\n\n{}", hot.print(limit)));
+            }
+            outputs.lock().unwrap().push(output)
+        }
+        Err(err) => eprintln!("Failed to combine stacks: {err}"),
+    }
+}
+
+/// add_local  -  add local variable of gdb `bt full` in line `s` to the last frame of `thread`.
+// Locals are indented by 8 spaces, values printed on several lines (`set print pretty`) are
+// indented further. Lines such as `No locals.` are skipped.
+fn add_local(thread: &mut Thread, s: &str) {
+    let Some(level) = thread
+        .frames
+        .last()
+        .and_then(|frame| patterns::frame_function().captures(frame))
+        .and_then(|m| m.name("level").unwrap().as_str().parse().ok())
+    else {
+        return;
     };
 
-    let r_match_empty = Regex::new(r#"^$"#).unwrap();
-    let r_match_entry = Regex::new(r#"\s*#\s*\d+\s+"#).unwrap();
-    let r_match_detach = Regex::new(r#"Inferior.*detached"#).unwrap();
+    let locals = &mut thread.vars.entry(level).or_default().locals;
+    if let Some(m) = patterns::gdb_local().captures(s) {
+        locals.push((
+            m.name("name").unwrap().as_str().to_owned(),
+            m.name("value").unwrap().as_str().to_owned(),
+        ));
+    } else if s.starts_with("         ") {
+        if let Some((_, value)) = locals.last_mut() {
+            value.push(' ');
+            value.push_str(s.trim());
+        }
+    }
+}
 
-    let mut cache: HashMap<String, String> = HashMap::new();
-    let mut tid = "";
-    let mut stack = "".to_owned();
-    let mut match_started = false;
+/// Parser of stack dumps, fed line by line.
+pub struct StackParser {
+    format: InputFormat,
+    source: Option<String>,
+    pid: Option<String>,
+    thread: Option<Thread>,
+    r_match_pid: Option<&'static Regex>,
+    r_match_tid: &'static Regex,
+    r_match_entry: &'static Regex,
+    r_match_detach: Option<&'static Regex>,
+    tid_group: &'static str,
+    // text that must exist in thread headers
+    tid_hint: &'static str,
+    /// Input is gdb `bt full`, with locals indented below frames.
+    locals: bool,
+}
 
-    for s in input.split('\n') {
-        if r_match_empty.find(s).is_some() {
-        } else if let Some(m) = r_match_tid.captures(s) {
-            match_started = true;
-            // start of new stack
-            if !tid.is_empty() {
-                let tids = match cache.get(&stack) {
-                    Some(existing) => format!("{}, {}", existing, tid.to_owned()),
-                    None => tid.to_owned(),
-                };
-                cache.insert(stack.to_string(), tids);
+impl StackParser {
+    pub fn new(format: InputFormat, source: Option<&str>, locals: bool) -> StackParser {
+        let (r_match_pid, r_match_tid, r_match_entry, r_match_detach, tid_group, tid_hint) =
+            match format {
+                InputFormat::EuStack => (
+                    Some(patterns::eustack_pid()),
+                    patterns::eustack_tid(),
+                    patterns::eustack_entry(),
+                    None,
+                    "tid",
+                    "TID",
+                ),
+                InputFormat::Gdb => (
+                    None,
+                    patterns::gdb_tid(),
+                    patterns::gdb_entry(),
+                    Some(patterns::gdb_detach()),
+                    "lwp",
+                    "LWP",
+                ),
+            };
+
+        StackParser {
+            format,
+            source: source.map(|s| s.to_owned()),
+            pid: None,
+            thread: None,
+            r_match_pid,
+            r_match_tid,
+            r_match_entry,
+            r_match_detach,
+            tid_group,
+            tid_hint,
+            locals,
+        }
+    }
+
+    /// feed  -  parse line `s`, returns previous thread when `s` starts a new one.
+    pub fn feed(&mut self, s: &str) -> Option<Thread> {
+        if s.is_empty() {
+            return None;
+        }
+
+        // locals of gdb `bt full` are indented, and may hold anything, even things looking like frames
+        if self.locals
+            && self.format == InputFormat::Gdb
+            && s.starts_with(char::is_whitespace)
+            && !patterns::gdb_frame_start().is_match(s)
+        {
+            if let Some(thread) = self.thread.as_mut() {
+                add_local(thread, s);
             }
+            return None;
+        }
 
-            tid = m.name("lwp").unwrap().as_str();
-            stack = "".to_string();
-        } else if r_match_entry.is_match(s) {
-            stack = stack + s + "\n";
-        } else if r_match_detach.is_match(s) || !match_started {
-            continue;
-        } else {
+        // cheap checks first, most lines are frames
+        if let Some(m) = self
+            .r_match_pid
+            .filter(|_| s.contains("PID"))
+            .and_then(|r| r.captures(s))
+        {
+            self.pid = Some(m.name("pid").unwrap().as_str().to_owned());
+        } else if let Some(m) = Some(self.r_match_tid)
+            .filter(|_| s.contains(self.tid_hint))
+            .and_then(|r| r.captures(s))
+        {
+            // start of new stack
+            let tid = m.name(self.tid_group).unwrap().as_str();
+            let mut thread = Thread::new(self.source.as_deref(), self.pid.as_deref(), tid);
+            if self.format == InputFormat::Gdb {
+                thread.name = patterns::gdb_thread_name()
+                    .captures(s)
+                    .map(|m| m.name("name").unwrap().as_str().to_owned());
+            }
+            return self.thread.replace(thread);
+        } else if self.r_match_entry.is_match(s) {
+            if let Some(thread) = self.thread.as_mut() {
+                thread.frames.push(s.to_owned());
+            }
+        } else if self.format == InputFormat::Gdb
+            && self.thread.is_some()
+            && !self.r_match_detach.as_ref().is_some_and(|r| r.is_match(s))
+        {
             eprintln!("IGNORE: Failed to parse: {s}");
         }
+
+        None
     }
 
-    // now handle last tid
-    let tids = match cache.get(&stack) {
-        Some(existing) => format!("{}, {}", existing, tid.to_owned()),
-        None => tid.to_owned(),
-    };
-    cache.insert(stack.to_string(), tids);
-    sort_and_print_stack(cache)
+    /// finish  -  returns the last thread, call this at the end of input.
+    pub fn finish(&mut self) -> Option<Thread> {
+        self.thread.take()
+    }
 }
 
-fn handle_content(contents: &str, raw: bool, unique: bool) {
-    Pager::new().setup();
-    let contents = if raw {
-        contents.to_owned()
-    } else {
-        simplify_stack(contents.to_owned())
-    };
+/// parse_eustack  -  parse threads from output of eu-stack.
+pub fn parse_eustack(input: &str, source: Option<&str>) -> Result<Vec<Thread>, String> {
+    InputFormat::EuStack.parse(input, source)
+}
+
+pub fn uniquify_eustack(input: &str, filter: &StackFilter) -> Result<String, String> {
+    print_unique(StackGroups::from_threads(
+        &parse_eustack(input, None)?,
+        filter,
+    ))
+}
+
+/// parse_gdb  -  parse threads from output of gdb `thread apply all backtrace`.
+pub fn parse_gdb(input: &str, source: Option<&str>) -> Result<Vec<Thread>, String> {
+    InputFormat::Gdb.parse(input, source)
+}
+
+pub fn uniquify_gdb(input: &str, filter: &StackFilter) -> Result<String, String> {
+    print_unique(StackGroups::from_threads(&parse_gdb(input, None)?, filter))
+}
+
+/// Number of lines to read after the first thread header before format detection is done.
+const DETECT_LINES: usize = 256;
+
+/// read_lines  -  call `handle` on each line of `reader`, without the line terminator.
+// Invalid UTF-8 sequences are replaced instead of being reported as errors, as dumps may contain
+// anything in function arguments.
+fn read_lines<R: BufRead, F>(mut reader: R, mut handle: F) -> Result<(), String>
+where
+    F: FnMut(&str) -> Result<(), String>,
+{
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                handle(line.trim_end_matches(['\n', '\r']))?;
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+}
+
+/// start_parser  -  create parser of detected format, and feed it with lines read so far.
+fn start_parser(
+    pending: &mut Vec<String>,
+    detector: &FormatDetector,
+    source: Option<&str>,
+    groups: &mut StackGroups,
+    count: &mut usize,
+) -> Result<StackParser, String> {
+    let mut parser = StackParser::new(detector.detect()?, source, false);
+    for s in pending.drain(..) {
+        if let Some(thread) = parser.feed(&s) {
+            groups.add(thread);
+            *count += 1;
+        }
+    }
+    Ok(parser)
+}
+
+/// parse_stream  -  parse stacks from `reader` line by line, and add threads into `groups`.
+// Input is never loaded as a whole: when `format` is not given, lines are buffered only from the
+// first thread header until format can be detected.
+pub fn parse_stream<R: BufRead>(
+    reader: R,
+    source: Option<&str>,
+    format: Option<InputFormat>,
+    raw: bool,
+    groups: &mut StackGroups,
+) -> Result<(), String> {
+    let mut parser = format.map(|f| StackParser::new(f, source, false));
+    let mut detector = FormatDetector::new();
+    let mut pending: Vec<String> = vec![];
+    let mut count = 0;
+    let maps = groups.filter.maps.clone();
+    let simplify = groups.filter.simplify.clone();
+    let mut symbolizer =
+        (!groups.filter.symbols.is_empty()).then(|| Symbolizer::new(&groups.filter.symbols));
 
-    if unique {
-        match (uniquify_eustack(&contents), uniquify_gdb(&contents)) {
-            (Ok(result), _) | (_, Ok(result)) => {
-                println!("{}", result);
+    read_lines(reader, |s| {
+        let s = match symbolizer.as_mut() {
+            Some(symbolizer) => symbolizer.line(&maps, s),
+            None => s.to_owned(),
+        };
+        let s = if raw { s } else { simplify_line(&s, &simplify) };
+
+        match parser.as_mut() {
+            Some(parser) => {
+                if let Some(thread) = parser.feed(&s) {
+                    groups.add(thread);
+                    count += 1;
+                }
             }
-            (_, Err(err)) => {
-                eprintln!("Failed to handle content: {}", err);
-                std::process::exit(2);
+            None => {
+                // lines before the first thread header carry no stack, skip them.
+                if detector.feed(&s) || !pending.is_empty() {
+                    pending.push(s);
+                }
+                if pending.len() >= DETECT_LINES {
+                    parser = Some(start_parser(
+                        &mut pending,
+                        &detector,
+                        source,
+                        groups,
+                        &mut count,
+                    )?);
+                }
             }
         }
+        Ok(())
+    })?;
+
+    let mut parser = match parser {
+        Some(parser) => parser,
+        None => start_parser(&mut pending, &detector, source, groups, &mut count)?,
+    };
+    if let Some(thread) = parser.finish() {
+        groups.add(thread);
+        count += 1;
+    }
+
+    if count == 0 {
+        Err(format!("not generated by {}", parser.format.name()))
     } else {
-        println!("{contents}");
+        Ok(())
     }
 }
 
+/// print_stream  -  print stacks from `reader` line by line, simplified unless `raw`.
+fn print_stream<R: BufRead>(reader: R, raw: bool, filter: &StackFilter) -> Result<(), String> {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut symbolizer = (!filter.symbols.is_empty()).then(|| Symbolizer::new(&filter.symbols));
+    read_lines(reader, |s| {
+        let s = match symbolizer.as_mut() {
+            Some(symbolizer) => symbolizer.line(&filter.maps, s),
+            None => s.to_owned(),
+        };
+        let s = filter.demangle.line(&s);
+        let result = if raw {
+            writeln!(out, "{s}")
+        } else {
+            writeln!(out, "{}", simplify_line(&s, &filter.simplify))
+        };
+        result.map_err(|err| err.to_string())
+    })
+}
+
+/// uniquify_stack_files  -  show stacks read from files or stdin.
+// Each file is parsed on its own, so files of different formats, or dumps of processes from
+// different hosts, can be merged together.
 pub async fn uniquify_stack_files(cli: Cli) {
-    let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(vec![]));
-    if cli.files.len() == 1 && cli.files[0] == "-" {
+    let from_stdin = cli.files.len() == 1 && cli.files[0] == "-";
+    if from_stdin {
         println!("Reading stack from STDIN.");
-        let stdin = std::io::stdin();
-        for line in std::io::BufRead::lines(stdin.lock()) {
-            if let Ok(line) = line {
-                lines.lock().unwrap().push(line);
+    } else {
+        println!("Reading stack from {} file(s).", cli.files.len());
+        for file in cli.files.iter() {
+            ensure_file_exists(file);
+        }
+    }
+
+    if !cli.tui {
+        Pager::new().setup();
+    }
+    let filter = StackFilter::from_cli(&cli);
+    if !cli.unique_mode {
+        for file in cli.files.iter() {
+            let result = if from_stdin {
+                print_stream(std::io::stdin().lock(), cli.raw_mode, &filter)
             } else {
-                eprint!("Error reading line.");
-                exit(2);
+                File::open(file)
+                    .map_err(|err| err.to_string())
+                    .and_then(|f| print_stream(BufReader::new(f), cli.raw_mode, &filter))
+            };
+
+            if let Err(err) = result {
+                eprintln!("failed to read from file {}, reason: {}", file, err);
             }
         }
+        exit(0);
+    }
+
+    let mut groups = StackGroups::new(filter.clone());
+    if from_stdin {
+        let stdin = std::io::stdin();
+        if let Err(err) = parse_stream(
+            stdin.lock(),
+            Some("-"),
+            cli.input_format,
+            cli.raw_mode,
+            &mut groups,
+        ) {
+            eprintln!("Failed to handle content: {err}");
+            exit(2);
+        }
     } else {
-        let n = cli.files.len();
         let mut handles = vec![];
-        println!("Reading stack from {n} file(s).");
-        for file in cli.files {
-            ensure_file_exists(&file);
-            let line_ref = lines.clone();
-            handles.push(tokio::spawn(async move {
-                match fs::read_to_string(&file).await {
-                    Ok(contents) => {
-                        line_ref.lock().unwrap().push(contents);
-                    }
-                    Err(err) => {
-                        eprint!("failed to read from file {}, reason: {}", file, err);
-                    }
-                }
+        for file in cli.files.clone() {
+            let format = cli.input_format;
+            let raw = cli.raw_mode;
+            let filter = filter.clone();
+            handles.push(tokio::task::spawn_blocking(move || {
+                let mut groups = StackGroups::new(filter);
+                let result = File::open(&file)
+                    .map_err(|err| format!("failed to read from file {}, reason: {}", file, err))
+                    .and_then(|f| {
+                        parse_stream(BufReader::new(f), Some(&file), format, raw, &mut groups)
+                            .map_err(|err| format!("Failed to handle content of {file}: {err}"))
+                    });
+                result.map(|_| groups)
             }));
         }
 
-        join_all(handles).await;
+        // merge in the order files are given
+        for handle in join_all(handles).await {
+            match handle.unwrap() {
+                Ok(parsed) => groups.merge(parsed),
+                Err(err) => {
+                    eprintln!("{err}");
+                    exit(2);
+                }
+            }
+        }
     }
 
-    let contents = lines.lock().unwrap().join("\n");
-    handle_content(&contents, cli.raw_mode, cli.unique_mode);
+    if cli.tui {
+        let (groups, notes) = groups.into_noted_groups();
+        if let Err(err) = browse(groups, notes) {
+            eprintln!("Failed to browse stacks: {err}");
+            exit(2);
+        }
+        exit(0);
+    }
+
+    match print_unique(groups) {
+        Ok(result) => {
+            println!("{}", result);
+        }
+        Err(err) => {
+            eprintln!("Failed to handle content: {}", err);
+            exit(2);
+        }
+    }
     exit(0);
 }
 
 #[test]
 fn test_regex_tid() {
-    let re = regex::Regex::new(RE_MATCH_GDB_TID).unwrap();
+    let re = patterns::gdb_tid();
 
     if let Some(m) = re.captures(r#"Thread 15 (Thread 0x7fa1aea006c0 (LWP 1175) "waybar"):"#) {
         assert_eq!(m.name("tid").unwrap().as_str(), "15");
         assert_eq!(m.name("lwp").unwrap().as_str(), "1175");
     } else {
-        assert!(false);
+        panic!();
     };
 
     if let Some(m) = re.captures(r#"Thread 13 (LWP 258729 "tokio-runtime-w"):"#) {
         assert_eq!(m.name("tid").unwrap().as_str(), "13");
         assert_eq!(m.name("lwp").unwrap().as_str(), "258729");
     } else {
-        assert!(false);
+        panic!();
     };
 }
 
+#[test]
+fn test_regex_thread_name() {
+    let re = patterns::gdb_thread_name();
+    for (header, name) in [
+        (
+            r#"Thread 15 (Thread 0x7fa1aea006c0 (LWP 1175) "waybar"):"#,
+            Some("waybar"),
+        ),
+        (
+            r#"Thread 13 (LWP 258729 "tokio-runtime-w"):"#,
+            Some("tokio-runtime-w"),
+        ),
+        (r#"Thread 1 (Thread 0x7f29ce816740 (LWP 37747)):"#, None),
+    ] {
+        assert_eq!(
+            re.captures(header)
+                .map(|m| m.name("name").unwrap().as_str()),
+            name
+        );
+    }
+}
+
 #[test]
 fn test_unquify() {
     let input = r#"
@@ -288,8 +918,9 @@ TID 14822:
 "#
     .to_owned();
 
-    assert!(uniquify_eustack(&input).is_ok());
-    assert!(uniquify_gdb(&input).is_err());
+    let filter = StackFilter::default();
+    assert!(uniquify_eustack(&input, &filter).is_ok());
+    assert!(uniquify_gdb(&input, &filter).is_err());
 
     let input = r#"
 Thread 3 (Thread 0x7f29ce816740 (LWP 37746) "test"):
@@ -329,8 +960,8 @@ Thread 1 (Thread 0x7f29ce816740 (LWP 37747) "test"):
 "#
     .to_owned();
 
-    assert!(uniquify_gdb(&input).is_ok());
-    assert!(uniquify_eustack(&input).is_err());
+    assert!(uniquify_gdb(&input, &filter).is_ok());
+    assert!(uniquify_eustack(&input, &filter).is_err());
 }
 
 #[test]
@@ -351,7 +982,248 @@ Thread 1 (Thread 0x7f29ce816740 (LWP 37747) "test"):
 "#
     .to_owned();
 
-    let result = simplify_stack(input);
+    let result = simplify_stack(input.clone(), &Simplify::Function);
     println!("{result}");
     assert!(result.find("in func1 () at").is_none());
+    assert!(simplify_stack(input.clone(), &Simplify::Location)
+        .contains("#4  0x000055723be8917d func1 at test.c:10\n"));
+    assert!(simplify_stack(input, &Simplify::Short(vec![])).contains("\n#4  func1 at test.c:10\n"));
+}
+
+#[test]
+fn test_merge_threads() {
+    let eustack = r#"
+PID 14794 - process
+TID 14794:
+#0  0x00007f83ddc5363f __poll
+#1  0x00007f83de32afa0 g_main_context_iteration
+TID 14795:
+#0  0x00007f83ddba6fea __sigtimedwait
+PID 14800 - process
+TID 14800:
+#0  0x00007f83ddc5363f __poll
+#1  0x00007f83de32afa0 g_main_context_iteration
+"#;
+    let gdb = r#"
+Thread 2 (Thread 0x7f29ce816740 (LWP 14795) "test"):
+#0  0x00007f83ddc5363f __poll
+#1  0x00007f83de32afa0 g_main_context_iteration
+"#;
+
+    let mut threads = parse_eustack(eustack, Some("host-a.txt")).unwrap();
+    assert_eq!(threads.len(), 3);
+    assert_eq!(threads[1].pid.as_deref(), Some("14794"));
+    assert_eq!(threads[2].pid.as_deref(), Some("14800"));
+
+    threads.extend(parse_gdb(gdb, Some("host-b.txt")).unwrap());
+    let groups = group_threads(&threads, &StackFilter::default());
+    assert_eq!(groups.len(), 2);
+    assert_eq!(
+        groups[0].1,
+        vec![
+            "host-a.txt:14794:14794",
+            "host-a.txt:14800:14800",
+            "host-b.txt:14795"
+        ]
+    );
+    assert_eq!(groups[1].1, vec!["host-a.txt:14794:14795"]);
+
+    let result = sort_and_print_stack(groups).unwrap();
+    assert!(result.starts_with("Number of thread: 3 -- "));
+
+    use crate::classify::ThreadClass;
+    let summary = StackGroups::from_threads(&threads, &StackFilter::default()).classes();
+    assert_eq!(summary.count("host-a.txt:14794", ThreadClass::Idle), 2);
+    assert_eq!(summary.count("host-a.txt:14800", ThreadClass::Idle), 1);
+    assert_eq!(summary.count("host-b.txt", ThreadClass::Idle), 1);
+
+    // threads of a single process are labelled with TID only
+    let groups = group_threads(
+        &parse_gdb(gdb, Some("host-b.txt")).unwrap(),
+        &StackFilter::default(),
+    );
+    assert_eq!(groups[0].1, vec!["14795"]);
+}
+
+#[test]
+fn test_parse_stream() {
+    let mut input = String::new();
+    for i in 0..1000 {
+        input.push_str(&format!("[New LWP {}]\n", 2000 + i));
+    }
+    for i in 0..1000 {
+        input.push_str(&format!(
+            "\nThread {} (Thread 0x7f29ce816740 (LWP {}) \"test\"):\n",
+            i + 1,
+            2000 + i
+        ));
+        input.push_str("#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6\n");
+        if i % 2 == 0 {
+            input.push_str("#1  0x000055723be89162 in func2 () at test.c:5\n");
+        }
+    }
+
+    let mut groups = StackGroups::default();
+    assert!(parse_stream(input.as_bytes(), Some("a"), None, false, &mut groups).is_ok());
+    let mut other = StackGroups::default();
+    assert!(parse_stream(
+        input.as_bytes(),
+        Some("b"),
+        Some(InputFormat::Gdb),
+        true,
+        &mut other
+    )
+    .is_ok());
+    groups.merge(other);
+
+    let groups = groups.into_groups();
+    assert_eq!(groups.len(), 4);
+    assert_eq!(
+        groups[0].0,
+        "#0  0x00007f29ce8db9e7 clock_nanosleep\n#1  0x000055723be89162 func2\n"
+    );
+    assert_eq!(groups[0].1.len(), 500);
+    assert_eq!(groups[0].1[0], "a:2000");
+
+    let mut groups = StackGroups::default();
+    let result = parse_stream(
+        input.as_bytes(),
+        None,
+        Some(InputFormat::EuStack),
+        false,
+        &mut groups,
+    );
+    assert_eq!(result, Err("not generated by eu-stack".to_owned()));
+    assert!(parse_stream("hello\nworld\n".as_bytes(), None, None, false, &mut groups).is_err());
+}
+
+#[test]
+fn test_simplify_line() {
+    let re = regex::Regex::new(r"\s+in\s+(?P<func>.*?)\s+\(.*?\)\s+(at|from)\s+.*").unwrap();
+    for s in [
+        "#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6",
+        "#3  0x000055723be89162 in func2 () at test.c:5",
+        "#6  0x000055723be891af in main (argc=1, argv=0x7ffec118b6f8) at test.c:19",
+        "#7  0x00007f29ce83f320 in ?? () from /usr/lib64/libc.so.6",
+        "#9  0x000055723be89085 in _start ()",
+        "#2  0x0000563 in std::function<void ()>::operator()() const (this=0x7f) at std_function.h:591",
+        "#4  Foo::bar (this=0x0, in=1) at foo.cc:10",
+        "#5  0x000055 in  spaced  (x=1)   at   a.c:1",
+        "#1  0x00007f83de32a8d7",
+        "#0  0x00007f83ddc5363f __poll",
+        "Thread 1 (Thread 0x7f29ce816740 (LWP 37747) \"test\"):",
+        "#8  0x00007f in within (in) from lib",
+        "#8  0x00007f in  () at a.c:1",
+        "#8  0x00007f in (a) (b) at a.c:1",
+        "",
+    ] {
+        let expected = re
+            .replace_all(s, |captures: &regex::Captures| {
+                format!(" {}", captures.name("func").unwrap().as_str())
+            })
+            .to_string();
+        assert_eq!(simplify_frame(s, false), expected, "{s}");
+    }
+
+    for (s, expected) in [
+        (
+            "#3  0x000055723be89162 in func2 () at test.c:5",
+            "#3  0x000055723be89162 func2 at test.c:5",
+        ),
+        (
+            "#6  0x000055723be891af in main (argc=1, argv=0x7ffec118b6f8) at  /src/test.c:19",
+            "#6  0x000055723be891af main at /src/test.c:19",
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6",
+            "#0  0x00007f29ce8db9e7 clock_nanosleep",
+        ),
+        ("#1  0x00007f83de32a8d7", "#1  0x00007f83de32a8d7"),
+    ] {
+        assert_eq!(simplify_frame(s, true), expected, "{s}");
+    }
+
+    let short = Simplify::Short(vec!["/home/me/repo/".to_owned()]);
+    for (s, expected) in [
+        (
+            "#6  0x000055723be891af in main (argc=1, argv=0x7ffec118b6f8) at /build/test.c:19",
+            "#6  main at test.c:19",
+        ),
+        (
+            "#12 0x000055723be891af in Foo::run (this=0x6) at /home/me/repo/src/foo.cc:7",
+            "#12 Foo::run at src/foo.cc:7",
+        ),
+        (
+            "#4  Foo::bar (this=0x0, in=1) at foo.cc:10",
+            "#4  Foo::bar at foo.cc:10",
+        ),
+        (
+            "#0  0x00007f29ce8db9e7 in clock_nanosleep () from /usr/lib64/libc.so.6",
+            "#0  clock_nanosleep",
+        ),
+        (
+            "#2  0x0000557b62997e8b signalThread(void*)",
+            "#2  signalThread(void*)",
+        ),
+        ("#1  0x00007f83de32a8d7", "#1  0x00007f83de32a8d7"),
+        ("TID 14794:", "TID 14794:"),
+    ] {
+        assert_eq!(simplify_line(s, &short), expected, "{s}");
+    }
+}
+
+#[test]
+fn test_noted_groups() {
+    use crate::signature::{fingerprint, parse_signatures, Signatures};
+
+    let mut thread = Thread::new(None, Some("100"), "101");
+    for frame in [
+        "#0  0x00007f29ce8db9e7 __lll_lock_wait",
+        "#1  0x00007f29ce8e2a55 pthread_mutex_lock",
+        "#2  0x000055723be89162 _ZN7storage5flushEv",
+    ] {
+        thread.frames.push(frame.to_owned());
+    }
+    let demangled = thread
+        .stack()
+        .replace("_ZN7storage5flushEv", "storage::flush()");
+    let signatures = Signatures {
+        signatures: parse_signatures(&format!(
+            "{:016x}  BUG-7  abort in flush",
+            fingerprint(&demangled)
+        ))
+        .unwrap(),
+        fingerprints: false,
+    };
+
+    // matched the same, whatever frames are shown
+    for filter in [
+        StackFilter::default(),
+        StackFilter {
+            demangle: Demangle::Off,
+            hide_frames: vec![Regex::new("pthread_").unwrap()],
+            ..Default::default()
+        },
+    ] {
+        let filter = StackFilter {
+            signatures: Some(signatures.clone()),
+            ..filter
+        };
+        let (groups, notes) =
+            StackGroups::from_threads(&[thread.clone()], &filter).into_noted_groups();
+        assert!(!groups[0].0.contains("BUG-7"));
+        assert!(notes[&groups[0].0].contains("BUG-7"));
+    }
+
+    // notes are shown, but not taken for frames
+    let output = print_unique(StackGroups::from_threads(
+        &[thread],
+        &StackFilter {
+            signatures: Some(signatures),
+            ..Default::default()
+        },
+    ))
+    .unwrap();
+    assert!(output.contains(" -- abort in flush  ["));
+    assert!(!output.contains("<---- HERE"));
 }
@@ -1,11 +1,16 @@
 use inquire::{MultiSelect, Select};
 use pager::Pager;
 use std::sync::{Arc, Mutex};
-use std::{ffi::OsStr, process::Stdio, sync::OnceLock};
+use std::{
+    ffi::OsStr,
+    process::Stdio,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use termion::terminal_size;
 use tokio::process::Command;
 
-use crate::args::Cli;
+use crate::{args::Cli, patterns};
 
 pub async fn execute_command<S, I>(
     command: &str,
@@ -30,8 +35,7 @@ where
 }
 
 fn parse_pid(s: &str) -> String {
-    let r_match_pid = regex::Regex::new(r#"\s*(?P<pid>\d+)\s+"#).unwrap();
-    let m = r_match_pid.captures(s).expect("capture fails");
+    let m = patterns::ps_pid().captures(s).expect("capture fails");
     m.name("pid").unwrap().as_str().to_string()
 }
 
@@ -56,7 +60,7 @@ async fn get_process_list(users: Option<String>) -> Result<Vec<String>, String>
             if code != 0 {
                 return Err(err);
             }
-            return Ok(out.split('\n').skip(1).map(|s| s.to_string()).collect());
+            Ok(out.split('\n').skip(1).map(|s| s.to_string()).collect())
         }
         Err(err) => Err(err.to_string()),
     }
@@ -210,6 +214,50 @@ pub fn setup_pager(cli: &Cli) {
     }
 }
 
+/// format_time  -  time of day of `time` in UTC, as HH:MM:SS.mmm.
+pub fn format_time(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since.subsec_millis()
+    )
+}
+
+/// format_date_time  -  date and time of day of `time` in UTC, as YYYY-MM-DD HH:MM:SS.
+// Days are converted to civil dates as in the proleptic Gregorian calendar, in eras of 400 years.
+pub fn format_date_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = secs / 86400 + 719468;
+    let (era, day) = (days / 146097, days % 146097);
+    let year_of_era = (day - day / 1460 + day / 36524 - day / 146096) / 365;
+    let day_of_year = day - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs % 86400 / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// sampling_prefix  -  header line describing sampling parameters.
+pub fn sampling_prefix(interval: Option<f32>, count: i32) -> String {
+    match interval {
+        Some(sleep) if count != 1 => format!("Interval: {}, Count: {}", sleep, count),
+        _ => "".to_owned(),
+    }
+}
+
 pub fn display_result(
     cli: &Cli,
     errors: Arc<Mutex<Vec<String>>>,
@@ -276,13 +324,17 @@ async fn test_list_process() {
 
     let result = get_process_list(Some("root".to_owned())).await;
     assert!(result.is_ok_and(|x| !x.is_empty()));
-    let mut cli = Cli::default();
-    cli.no_pager = true;
+    let cli = Cli {
+        no_pager: true,
+        ..Default::default()
+    };
     list_process(cli).await;
 
-    let mut cli = Cli::default();
-    cli.no_pager = true;
-    cli.files.push("cs".to_owned());
+    let cli = Cli {
+        no_pager: true,
+        files: vec!["cs".to_owned()],
+        ..Default::default()
+    };
     list_process(cli).await;
 }
 
@@ -296,3 +348,13 @@ async fn test_parse_and_get_pid() {
     let s = terminal_size();
     println!("S: {:?}", s);
 }
+
+#[test]
+fn test_format_time() {
+    let time =
+        UNIX_EPOCH + std::time::Duration::from_millis(((3 * 24 + 13) * 3600 + 65) * 1000 + 42);
+    assert_eq!(format_time(time), "13:01:05.042");
+    assert_eq!(format_date_time(time), "1970-01-04 13:01:05");
+    let time = UNIX_EPOCH + std::time::Duration::from_secs(1709210096);
+    assert_eq!(format_date_time(time), "2024-02-29 12:34:56");
+}
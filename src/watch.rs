@@ -0,0 +1,248 @@
+// Watch mode: sample processes again and again, redrawing unique stacks like `top` does.
+// Groups are followed across refreshes by their functions, so changes since the last refresh can
+// be highlighted even though addresses differ between processes.
+
+use colored::*;
+use futures::future::join_all;
+use std::{collections::HashMap, io::Write, process::exit, time::Instant};
+use termion::{clear, cursor};
+
+use crate::{
+    args::Cli,
+    eu_stack,
+    filter::StackFilter,
+    gdb,
+    stack::{function_name, Thread},
+    uniquify::{Notes, StackGroups},
+};
+
+/// Interval between refreshes, when not given.
+const WATCH_INTERVAL: f32 = 1.0;
+
+/// How a group changed since the last refresh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    New,
+    // difference of number of threads, 0 if unchanged
+    Threads(i64),
+}
+
+/// A unique stack, as shown in one refresh.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchRow {
+    pub stack: String,
+    pub labels: Vec<String>,
+    /// Number of threads seen in this group over all refreshes.
+    pub samples: usize,
+    pub change: Change,
+}
+
+/// group_key  -  functions of `stack`, used to follow a group across refreshes.
+fn group_key(stack: &str) -> String {
+    stack
+        .lines()
+        .map(function_name)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Groups seen by previous refreshes.
+#[derive(Debug, Default)]
+pub struct WatchState {
+    pub refreshes: usize,
+    samples: HashMap<String, usize>,
+    previous: HashMap<String, usize>,
+}
+
+impl WatchState {
+    /// update  -  account groups of a new refresh, returns them ordered by number of threads,
+    /// then by number of samples.
+    pub fn update(&mut self, groups: Vec<(String, Vec<String>)>) -> Vec<WatchRow> {
+        self.refreshes += 1;
+        let mut current = HashMap::new();
+        let mut rows: Vec<WatchRow> = groups
+            .into_iter()
+            .map(|(stack, labels)| {
+                let key = group_key(&stack);
+                let samples = self.samples.entry(key.clone()).or_default();
+                *samples += labels.len();
+                let change = match self.previous.get(&key) {
+                    Some(n) => Change::Threads(labels.len() as i64 - *n as i64),
+                    None if self.refreshes > 1 => Change::New,
+                    None => Change::Threads(0),
+                };
+                *current.entry(key).or_default() += labels.len();
+                WatchRow {
+                    samples: *samples,
+                    stack,
+                    labels,
+                    change,
+                }
+            })
+            .collect();
+        self.previous = current;
+
+        rows.sort_by(|r1, r2| {
+            (r2.labels.len(), r2.samples)
+                .cmp(&(r1.labels.len(), r1.samples))
+                .then(r1.stack.cmp(&r2.stack))
+        });
+        rows
+    }
+}
+
+/// render_rows  -  rows of a refresh, with `notes` of stacks, cut to `height` lines of `width`
+/// columns.
+pub fn render_rows(
+    header: &str,
+    rows: &[WatchRow],
+    notes: &Notes,
+    width: usize,
+    height: usize,
+) -> String {
+    let mut lines = vec![header.to_owned(), String::new()];
+    for row in rows {
+        let marker = match row.change {
+            Change::New => "NEW".green().bold().to_string(),
+            Change::Threads(0) => "   ".to_owned(),
+            Change::Threads(d) if d > 0 => format!("{:<3}", format!("+{d}")).yellow().to_string(),
+            Change::Threads(d) => format!("{d:<3}").cyan().to_string(),
+        };
+        let labels: String = row.labels.join(", ").chars().take(width).collect();
+        lines.push(format!(
+            "{marker} Threads: {:>4}  Samples: {:>6} -- {labels}",
+            row.labels.len(),
+            row.samples,
+        ));
+        lines.extend(
+            row.stack
+                .lines()
+                .chain(notes.get(&row.stack).map(|s| s.as_str()))
+                .map(|s| s.chars().take(width).collect::<String>()),
+        );
+        lines.push(String::new());
+    }
+
+    lines.truncate(height.max(1));
+    lines.join("\n")
+}
+
+/// sample_threads  -  threads of all `pids`, sampled once, and pids that failed.
+async fn sample_threads(
+    pids: &[String],
+    gdb_mode: bool,
+    raw: bool,
+    filter: &StackFilter,
+) -> (Vec<Thread>, Vec<String>) {
+    let results = join_all(pids.iter().map(|pid| async move {
+        if gdb_mode {
+            gdb::sample_threads(pid, raw, filter).await
+        } else {
+            eu_stack::sample_threads(pid, filter).await
+        }
+    }))
+    .await;
+
+    let mut threads = vec![];
+    let mut errors = vec![];
+    for (pid, result) in pids.iter().zip(results) {
+        match result {
+            Ok(parsed) => threads.extend(parsed),
+            Err(_) => errors.push(pid.clone()),
+        }
+    }
+    (threads, errors)
+}
+
+/// run_watch  -  sample processes every interval until interrupted, redrawing unique stacks.
+pub async fn run_watch(cli: &Cli) {
+    let Some(pids) = cli.pids.clone() else {
+        eprintln!("Needs pid to watch.");
+        exit(2);
+    };
+
+    let interval = cli.interval.unwrap_or(WATCH_INTERVAL);
+    let filter = StackFilter::from_cli(cli);
+    let mut state = WatchState::default();
+    let start = Instant::now();
+    loop {
+        let (threads, errors) = sample_threads(&pids, cli.gdb_mode, cli.raw_mode, &filter).await;
+        if errors.len() == pids.len() {
+            eprintln!("Failed to sample process: {}", errors.join(", "));
+            exit(2);
+        }
+
+        let (groups, notes) = StackGroups::from_threads(&threads, &filter).into_noted_groups();
+        let rows = state.update(groups);
+        let mut header = format!(
+            "Every {interval}s: {}    refresh: {}    elapsed: {:.1}s",
+            pids.join(", "),
+            state.refreshes,
+            start.elapsed().as_secs_f32()
+        );
+        if !errors.is_empty() {
+            header.push_str(
+                &format!("    failed: {}", errors.join(", "))
+                    .red()
+                    .to_string(),
+            );
+        }
+        let (width, height) =
+            termion::terminal_size().map_or((80, 24), |(w, h)| (w as usize, h as usize));
+        print!(
+            "{}{}{}",
+            clear::All,
+            cursor::Goto(1, 1),
+            render_rows(&header, &rows, &notes, width, height)
+        );
+        let _ = std::io::stdout().flush();
+
+        tokio::time::sleep(tokio::time::Duration::from_secs_f32(interval)).await;
+    }
+}
+
+#[test]
+fn test_watch_state() {
+    let labels = |tids: &[&str]| tids.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let mut state = WatchState::default();
+
+    let rows = state.update(vec![
+        ("#0  0x1 __poll\n#1  0x2 main\n".to_owned(), labels(&["1"])),
+        ("#0  0x1 epoll_wait\n".to_owned(), labels(&["2", "3"])),
+    ]);
+    assert_eq!(rows[0].stack, "#0  0x1 epoll_wait\n");
+    assert_eq!(rows[0].samples, 2);
+    assert_eq!(rows[0].change, Change::Threads(0));
+    assert_eq!(rows[1].change, Change::Threads(0));
+
+    // addresses may differ between refreshes, when groups merge threads of several processes
+    let rows = state.update(vec![
+        (
+            "#0  0x9 __poll\n#1  0x8 main\n".to_owned(),
+            labels(&["1", "4", "5"]),
+        ),
+        ("#0  0x1 epoll_wait\n".to_owned(), labels(&["2"])),
+        ("#0  0x1 __lll_lock_wait\n".to_owned(), labels(&["3"])),
+    ]);
+    assert_eq!(state.refreshes, 2);
+    assert_eq!(
+        rows.iter()
+            .map(|r| (r.samples, r.change))
+            .collect::<Vec<_>>(),
+        vec![
+            (4, Change::Threads(2)),
+            (3, Change::Threads(-1)),
+            (1, Change::New)
+        ]
+    );
+
+    let output = render_rows("header", &rows, &Notes::new(), 80, 100);
+    assert!(output.starts_with("header\n\n"));
+    assert!(output.contains("Threads:    3  Samples:      4 -- 1, 4, 5\n#0  0x9 __poll\n"));
+    assert_eq!(
+        render_rows("header", &rows, &Notes::new(), 80, 3)
+            .lines()
+            .count(),
+        3
+    );
+}